@@ -0,0 +1,71 @@
+/*
+    Сериализация сумм u128 в JSON десятичной строкой: #[serde(with = "crate::amount")].
+    serde_json без arbitrary_precision не может представить в serde_json::Value числа больше u64::MAX,
+    а через Value строятся ответы RPC и сообщения сети. Строка передает любую сумму без потери точности.
+    При чтении принимается и строка, и целое число, чтобы клиенты могли передавать небольшие суммы числом.
+*/
+use std::fmt;
+use serde::{Deserializer, Serializer};
+use serde::de::{self, Visitor};
+
+pub fn serialize<S: Serializer>(amount: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(amount)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+    deserializer.deserialize_any(AmountVisitor)
+}
+
+struct AmountVisitor;
+
+impl Visitor<'_> for AmountVisitor {
+    type Value = u128;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a non-negative integer or a decimal string")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u128, E> {
+        Ok(value as u128)
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> Result<u128, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<u128, E> {
+        u128::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u128, E> {
+        value.parse().map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Amount {
+        #[serde(with = "super")]
+        value: u128,
+    }
+
+    #[test]
+    fn large_amounts_round_trip_as_strings() {
+        let amount = Amount { value: u128::MAX };
+        let value = serde_json::to_value(&amount).unwrap();
+        assert_eq!(value, json!({ "value": u128::MAX.to_string() }));
+        assert_eq!(serde_json::from_value::<Amount>(value).unwrap(), amount);
+    }
+
+    #[test]
+    fn numbers_are_accepted_and_garbage_is_rejected() {
+        assert_eq!(serde_json::from_value::<Amount>(json!({ "value": 42 })).unwrap(), Amount { value: 42 });
+        assert!(serde_json::from_value::<Amount>(json!({ "value": -1 })).is_err());
+        assert!(serde_json::from_value::<Amount>(json!({ "value": "1.5" })).is_err());
+        assert!(serde_json::from_value::<Amount>(json!({ "value": 1.5 })).is_err());
+    }
+}
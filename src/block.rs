@@ -1,45 +1,97 @@
 use std::time::{SystemTime, UNIX_EPOCH};
-use sha2::{Sha256, Digest};
 use crate::transaction::Transaction;
+use crate::encoding::{hash_hex, Encoder, BLOCK_HEADER_DOMAIN};
+use crate::merkle;
+use crate::keys;
+use ed25519_dalek::Keypair;
 use serde::Deserialize;
 use serde::Serialize;
 
+// Заголовок блока. Хеш блока считается только от заголовка, транзакции входят в него через корень Меркла
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Block {
+pub struct BlockHeader {
     pub index: u64,
     pub timestamp: u128,
     pub previous_hash: String,
-    pub hash: String,
+    pub transactions_root: String,
     pub nonce: u64,
+    // Открытый ключ валидатора, создавшего блок (base64)
+    pub validator: String,
+    // Слот PoS, в котором создан блок
+    pub slot: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub hash: String,
+    // Подпись заголовка ключом валидатора (base64). Генезис-блок не подписывается
+    #[serde(default)]
+    pub signature: String,
     pub transactions: Vec<Transaction>,
 }
 
+impl BlockHeader {
+    // Каноническое кодирование заголовка блока
+    pub fn encode(&self) -> Vec<u8> {
+        Encoder::new(BLOCK_HEADER_DOMAIN)
+            .u64(self.index)
+            .u128(self.timestamp)
+            .str(&self.previous_hash)
+            .str(&self.transactions_root)
+            .u64(self.nonce)
+            .str(&self.validator)
+            .u64(self.slot)
+            .finish()
+    }
+
+    pub fn calculate_hash(&self) -> String {
+        hash_hex(&self.encode())
+    }
+}
+
 impl Block {
-    pub fn new(index: u64, previous_hash: String, transactions: Vec<Transaction>) -> Self {
+    pub fn new(index: u64, previous_hash: String, transactions: Vec<Transaction>, validator: String, slot: u64) -> Self {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards")
             .as_millis();
-        
-        let nonce = 0; 
-        let hash = Self::calculate_hash(index, timestamp, &previous_hash, nonce, &transactions);
-        
-        Block {
+
+        let header = BlockHeader {
             index,
             timestamp,
             previous_hash,
+            transactions_root: Self::calculate_transactions_root(&transactions),
+            nonce: 0,
+            validator,
+            slot,
+        };
+        let hash = header.calculate_hash();
+
+        Block {
+            header,
             hash,
-            nonce,
+            signature: String::new(),
             transactions,
         }
     }
 
-    pub fn calculate_hash(index: u64, timestamp: u128, previous_hash: &str, nonce: u64, transactions: &Vec<Transaction>) -> String {
-        let transactions_json = serde_json::to_string(transactions).expect("Error serializing transactions");
-        let input = format!("{}{}{}{}{}", index, timestamp, previous_hash, nonce, transactions_json);
-        let mut hasher = Sha256::new();
-        hasher.update(input);
-        let result = hasher.finalize();
-        format!("{:x}", result)
+    // Подписывает заголовок блока ключом валидатора
+    pub fn sign(&mut self, keypair: &Keypair) {
+        self.signature = keys::sign(keypair, &self.header.encode());
+    }
+
+    // Проверяет, что заголовок подписан ключом из поля validator
+    pub fn verify_signature(&self) -> bool {
+        keys::verify(&self.header.validator, &self.header.encode(), &self.signature)
+    }
+
+    pub fn transaction_hashes(&self) -> Vec<String> {
+        self.transactions.iter().map(|tx| tx.hash.clone()).collect()
+    }
+
+    pub fn calculate_transactions_root(transactions: &[Transaction]) -> String {
+        let hashes: Vec<&str> = transactions.iter().map(|tx| tx.hash.as_str()).collect();
+        merkle::merkle_root(&hashes)
     }
 }
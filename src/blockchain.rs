@@ -1,107 +1,593 @@
 // src/blockchain.rs
-use crate::block::Block;
+use crate::block::{Block, BlockHeader};
 use crate::transaction::Transaction;
-use serde::Deserialize;
-use serde::Serialize;
-use crate::transaction::Mempool;
+use crate::transaction::{BlockLimits, Mempool, MempoolError};
+use crate::storage::SharedBlockStore;
+use crate::state::{StateError, WorldState, BLOCK_REWARD, NETWORK_ADDRESS};
+use crate::pos::PoS;
+use crate::keys;
+use crate::validation::{self, ImportError};
+use crate::fork_choice::BlockTree;
+use crate::events::{ChainEvent, EventBus};
+use ed25519_dalek::Keypair;
+use tcp_module::hello::ChainHead;
+use tcp_module::message::{Message, MessageType};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
 use tokio::sync::Mutex;
 use std::sync::Arc;
+use std::collections::{BTreeMap, HashSet};
 use tokio::time::{sleep, Duration};
-use std::time::{SystemTime, UNIX_EPOCH};
-use log::info;
+use log::{debug, error, info, warn};
 
 #[derive(Clone,)]
 pub struct Blockchain {
+    // Основная цепочка
     pub chain: Arc<Mutex<Vec<Block>>>,
-    pub pending_transactions: Vec<Transaction>,
+    // Дерево всех известных блоков, включая боковые ветки
+    tree: Arc<Mutex<BlockTree>>,
     mempool: Arc<Mutex<Mempool>>,
+    // Хранилище блоков на диске. Каждый новый блок сначала записывается в него, затем в chain
+    store: SharedBlockStore,
+    // Балансы аккаунтов после последнего блока
+    state: Arc<Mutex<WorldState>>,
+    // Набор валидаторов и расписание лидеров слотов
+    pos: Arc<Mutex<PoS>>,
+    // Ключ узла, которым подписываются создаваемые блоки
+    keypair: Arc<Keypair>,
+    // Адрес узла (открытый ключ), от имени которого создаются блоки
+    validator_address: String,
+    // Очередь сообщений для рассылки другим узлам
+    send_to_nodes_link: Sender<Message>,
+    // Последний блок основной цепочки для приветствия Hello сетевого модуля
+    head: watch::Sender<ChainHead>,
+    // Параметры создания блоков
+    producer: ProducerConfig,
+    // Шина событий, в которую сообщается о блоках основной цепочки
+    events: EventBus,
+}
+
+// Параметры создания блоков
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProducerConfig {
+    pub limits: BlockLimits,
+    // Создавать блоки без транзакций, чтобы цепочка росла и при пустом мемпуле
+    pub empty_blocks: bool,
 }
 
 impl Blockchain {
-    pub fn new(mempool: Arc<Mutex<Mempool>>, chain: Arc<Mutex<Vec<Block>>>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        mempool: Arc<Mutex<Mempool>>,
+        chain: Arc<Mutex<Vec<Block>>>,
+        tree: Arc<Mutex<BlockTree>>,
+        store: SharedBlockStore,
+        state: Arc<Mutex<WorldState>>,
+        pos: Arc<Mutex<PoS>>,
+        keypair: Arc<Keypair>,
+        send_to_nodes_link: Sender<Message>,
+        head: watch::Sender<ChainHead>,
+    ) -> Self {
+        let validator_address = keys::address(&keypair.public);
         Blockchain {
-            chain: chain,
-            pending_transactions: Vec::new(),
-            mempool: mempool,
+            chain,
+            tree,
+            mempool,
+            store,
+            state,
+            pos,
+            keypair,
+            validator_address,
+            send_to_nodes_link,
+            head,
+            producer: ProducerConfig::default(),
+            events: EventBus::new(),
+        }
+    }
+
+    pub fn with_producer(mut self, producer: ProducerConfig) -> Self {
+        self.producer = producer;
+        self
+    }
+
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    // Сообщает сетевому модулю новую вершину основной цепочки и ее вес
+    fn publish_head(&self, chain: &[Block], tree: &BlockTree) {
+        if let Some(last) = chain.last() {
+            let weight = head_weight(tree, &last.hash);
+            self.head.send_replace(ChainHead { height: last.header.index, hash: last.hash.clone(), weight });
         }
     }
-    // Основной цикл блокчейна
+
+    fn current_time() -> u128 {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis()
+    }
+
+    /*
+        Основной цикл блокчейна. Просыпается в начале каждого слота PoS
+        и создает блок, если данный узел является лидером слота.
+    */
     pub async fn start_thread(&mut self) {
         info!("Blockchain started.");
-       
-        let mut time_block_loop = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+        let slot_duration = self.pos.lock().await.slot_duration;
 
         loop {
-            let transaction; 
-            
-            {
-                let mut mempool_lock = self.mempool.lock().await;
-                transaction = mempool_lock.get_highest_fee_transaction();
-            }
-        
-            if let Some(tx) = transaction {
-                self.add_transaction(tx);
-                info!("Pending Transactions size: {}", self.pending_transactions.len());
-            } else {
-                sleep(Duration::from_secs(1)).await;
-            }
+            let now = Self::current_time();
+            let next_slot_start = (now / slot_duration + 1) * slot_duration;
+            sleep(Duration::from_millis((next_slot_start - now) as u64)).await;
 
-            if self.pending_transactions.len() > 0 {
-                let current_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
-                if current_time - time_block_loop > 20000 {
-                    self.mine_pending_transactions("0x000000000000000000000".to_string()).await;
-                    time_block_loop = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
-                }
-            }
-            
+            let slot = self.pos.lock().await.slot_at(Self::current_time());
+            self.produce_block(slot).await;
         }
     }
 
-    pub fn create_genesis_block() -> Block {
-        Block::new(0, String::from("0"), Vec::new())
+    // Генезис-блок содержит начальные балансы в виде транзакций от сети
+    pub fn create_genesis_block(balances: &BTreeMap<String, u128>) -> Block {
+        let transactions = balances
+            .iter()
+            .map(|(address, amount)| Transaction::new(NETWORK_ADDRESS.to_string(), address.clone(), *amount, 0, 0, 0))
+            .collect();
+        let mut block = Block::new(0, String::from("0"), transactions, String::new(), 0);
+        // Время генезиса фиксировано, чтобы узлы с одинаковыми начальными балансами имели одинаковый генезис
+        block.header.timestamp = 0;
+        block.hash = block.header.calculate_hash();
+        block
+    }
+
+    // Хеш генезис-блока. Узлы с разным генезисом принадлежат разным сетям
+    pub async fn genesis_hash(&self) -> String {
+        let chain = self.chain.lock().await;
+        chain.first().expect("Blockchain should have at least one block").hash.clone()
+    }
+
+    // Высота и хеш последнего блока основной цепочки
+    pub async fn best(&self) -> (u64, String) {
+        let chain = self.chain.lock().await;
+        let head = chain.last().expect("Blockchain should have at least one block");
+        (head.header.index, head.hash.clone())
+    }
+
+    // Вес основной цепочки по правилу выбора
+    pub async fn best_weight(&self) -> u64 {
+        let chain = self.chain.lock().await;
+        let tree = self.tree.lock().await;
+        head_weight(&tree, &chain.last().expect("Blockchain should have at least one block").hash)
+    }
+
+    // true, если блок есть в основной цепочке или в боковых ветках
+    pub async fn knows(&self, hash: &str) -> bool {
+        self.tree.lock().await.contains(hash)
+    }
+
+    // Заголовки основной цепочки начиная с высоты from, не больше max штук
+    pub async fn headers(&self, from: u64, max: usize) -> Vec<(String, BlockHeader)> {
+        let chain = self.chain.lock().await;
+        chain
+            .iter()
+            .skip(from as usize)
+            .take(max)
+            .map(|block| (block.hash.clone(), block.header.clone()))
+            .collect()
+    }
+
+    // Известные узлу блоки с указанными хешами
+    pub async fn blocks_by_hash(&self, hashes: &[String]) -> Vec<Block> {
+        let chain = self.chain.lock().await;
+        let tree = self.tree.lock().await;
+        hashes.iter().filter_map(|hash| tree.get_block(hash, &chain)).collect()
     }
 
-    pub fn add_transaction(&mut self, transaction: Transaction) {
-        self.pending_transactions.push(transaction);
+    // Возвращает в мемпул транзакции, не попавшие в блок
+    async fn return_to_mempool(&self, transactions: Vec<Transaction>, state: &WorldState) {
+        let mut mempool = self.mempool.lock().await;
+        for tx in transactions.into_iter().filter(|tx| tx.addr != NETWORK_ADDRESS) {
+            let hash = tx.hash.clone();
+            match mempool.return_transaction(tx, state) {
+                Ok(()) | Err(MempoolError::Duplicate) => {}
+                Err(e) => {
+                    warn!("Transaction {} dropped: {}", hash, e);
+                    mempool.record_dropped(&hash, e);
+                }
+            }
+        }
     }
 
-    pub async fn mine_pending_transactions(&mut self, validator_address: String) {
-        self.add_transaction(Transaction::new("network".to_string(), validator_address, 50, 0, 0));
-        //// timestamp нужно указать
-        
+    /*
+        Создает блок в слоте slot, если данный узел является лидером этого слота.
+        Транзакции забираются из мемпула пачкой в пределах ограничений размера блока.
+    */
+    pub async fn produce_block(&mut self, slot: u64) {
         let mut chain = self.chain.lock().await;
+        let mut state = self.state.lock().await;
+        let pos = self.pos.lock().await;
 
         let previous_block = chain.last().expect("Blockchain should have at least one block");
-        let transactions = self.pending_transactions.clone();
-        let new_block = Block::new(previous_block.index + 1, previous_block.hash.clone(), transactions);
+        match pos.select_validator(&previous_block.hash, slot) {
+            Some(leader) if leader.address == self.validator_address => {}
+            Some(leader) => {
+                debug!("Slot {} belongs to validator {}", slot, leader.address);
+                return;
+            }
+            None => {
+                warn!("No validators available for slot {}", slot);
+                return;
+            }
+        }
+        let validator_address = self.validator_address.clone();
+
+        let mut batch = self.mempool.lock().await.take_batch(&self.producer.limits);
+        if batch.is_empty() && !self.producer.empty_blocks {
+            return;
+        }
+        // Транзакции одного отправителя должны исполняться по возрастанию nonce
+        batch.sort_by_key(|tx| tx.nonce);
 
+        // Применяем транзакции к копии состояния, транзакции без покрытия в блок не попадают.
+        // Транзакции, ожидающие предыдущий nonce, возвращаются в мемпул.
+        let mut scratch = state.clone();
+        let mut transactions = Vec::new();
+        let mut deferred = Vec::new();
+        for tx in batch {
+            match scratch.apply_transaction(&tx, &validator_address) {
+                Ok(()) => transactions.push(tx),
+                Err(StateError::NonceTooHigh { .. }) => deferred.push(tx),
+                Err(e) => {
+                    warn!("Transaction {} dropped: {}", tx.hash, e);
+                    self.mempool.lock().await.record_dropped(&tx.hash, e);
+                }
+            }
+        }
+        if transactions.is_empty() && !self.producer.empty_blocks {
+            self.return_to_mempool(deferred, &state).await;
+            return;
+        }
+
+        let index = previous_block.header.index + 1;
+        // В качестве nonce награды используется номер блока, чтобы хеши наград не совпадали
+        transactions.push(Transaction::new(NETWORK_ADDRESS.to_string(), validator_address.clone(), BLOCK_REWARD, 0, 0, index));
+
+        let mut new_block = Block::new(index, previous_block.hash.clone(), transactions, validator_address, slot);
+        new_block.sign(&self.keypair);
+
+        // Слот мог закончиться, пока блок собирался
+        if !pos.verify_leader(&new_block.header, &previous_block.hash) {
+            warn!("Slot {} is over, block {} discarded", slot, index);
+            deferred.extend(new_block.transactions);
+            self.return_to_mempool(deferred, &state).await;
+            return;
+        }
+
+        let mut next_state = state.clone();
+        if let Err(e) = next_state.apply_block(&new_block) {
+            error!("Block {} rejected by state: {}", new_block.header.index, e);
+            deferred.extend(new_block.transactions);
+            self.return_to_mempool(deferred, &state).await;
+            return;
+        }
+
+        if let Err(e) = self.store.lock().await.append(&new_block) {
+            error!("Failed to write block {} to store: {}", new_block.header.index, e);
+            // Транзакции попадут в следующую попытку
+            deferred.extend(new_block.transactions);
+            self.return_to_mempool(deferred, &state).await;
+            return;
+        }
+
+        let mut tree = self.tree.lock().await;
+        tree.insert_canonical(&new_block, &pos);
+        match serde_json::to_value(&new_block) {
+            Ok(data) => {
+                if let Err(e) = self.send_to_nodes_link.try_send(Message::new(MessageType::Block, data)) {
+                    warn!("Failed to send block {} to nodes: {}", new_block.header.index, e);
+                }
+            }
+            Err(e) => error!("Failed to serialize block {}: {}", new_block.header.index, e),
+        }
+        info!("Block number {} created with {} transactions.", new_block.header.index, new_block.transactions.len() - 1);
+        self.events.emit(ChainEvent::NewBlock(new_block.clone()));
         chain.push(new_block);
-        self.pending_transactions.clear();
-        info!("Block number {} created successfully.", chain.len());
+        self.publish_head(&chain, &tree);
+        drop(tree);
+        *state = next_state;
+
+        self.return_to_mempool(deferred, &state).await;
     }
 
     pub async fn is_valid(&self) -> bool {
         let chain = self.chain.lock().await;
-        
+        let pos = self.pos.lock().await;
+        let now = Self::current_time();
+
         for i in 1..chain.len() {
             let previous_block = &chain[i - 1];
             let current_block = &chain[i];
-            
-            if current_block.previous_hash != previous_block.hash {
-                return false;
-            }
 
-            if current_block.hash != Block::calculate_hash(
-                current_block.index,
-                current_block.timestamp,
-                &current_block.previous_hash,
-                current_block.nonce,
-                &current_block.transactions,
-            ) {
+            if let Err(e) = validation::check_block(current_block, now)
+                .and_then(|_| validation::check_block_context(current_block, previous_block, &pos))
+            {
+                warn!("Block {} is invalid: {}", current_block.header.index, e);
                 return false;
             }
         }
         true
     }
+
+    // Импортирует блок из сообщения другого узла
+    pub async fn import_message(&self, message: &Message) -> Result<(), ImportError> {
+        let block: Block = message.payload().map_err(|e| ImportError::Malformed(e.to_string()))?;
+        self.import_block(block).await
+    }
+
+    /*
+        Проверяет блок, полученный от другого узла, и добавляет его в дерево блоков.
+        Если блок продолжает основную цепочку, он сразу применяется к состоянию.
+        Если блок создает или продолжает боковую ветку, которая стала тяжелее основной, выполняется реорганизация.
+    */
+    pub async fn import_block(&self, block: Block) -> Result<(), ImportError> {
+        validation::check_block(&block, Self::current_time())?;
+
+        let mut chain = self.chain.lock().await;
+        let mut tree = self.tree.lock().await;
+        let mut state = self.state.lock().await;
+        let pos = self.pos.lock().await;
+
+        if tree.contains(&block.hash) {
+            return Err(ImportError::AlreadyKnown);
+        }
+        let parent = match tree.get_block(&block.header.previous_hash, &chain) {
+            Some(parent) => parent,
+            None => return Err(ImportError::UnknownParent { previous_hash: block.header.previous_hash.clone() }),
+        };
+        validation::check_block_context(&block, &parent, &pos)?;
+
+        let head = chain.last().expect("Blockchain should have at least one block").hash.clone();
+        if parent.hash != head {
+            let hash = block.hash.clone();
+            tree.insert_side(block, &pos);
+            if !tree.is_heavier(&hash, &head) {
+                info!("Block {} stored in a side branch", hash);
+                return Ok(());
+            }
+            return self.reorganize(&mut chain, &mut tree, &mut state, &hash).await;
+        }
+
+        let mut next_state = state.clone();
+        next_state.apply_block(&block).map_err(ImportError::State)?;
+
+        self.store
+            .lock()
+            .await
+            .append(&block)
+            .map_err(|e| ImportError::Storage(e.to_string()))?;
+
+        {
+            let mut mempool = self.mempool.lock().await;
+            for tx in &block.transactions {
+                mempool.remove_transaction(&tx.hash);
+            }
+        }
+
+        info!("Block number {} imported from validator {}", block.header.index, block.header.validator);
+        tree.insert_canonical(&block, &pos);
+        self.events.emit(ChainEvent::NewBlock(block.clone()));
+        chain.push(block);
+        self.publish_head(&chain, &tree);
+        *state = next_state;
+        Ok(())
+    }
+
+    /*
+        Переключает основную цепочку на боковую ветку с вершиной new_head.
+        Состояние пересчитывается от генезиса до общего предка и затем по блокам новой ветки.
+        Транзакции из блоков старой ветки, не попавшие в новую, возвращаются в мемпул.
+    */
+    async fn reorganize(&self, chain: &mut Vec<Block>, tree: &mut BlockTree, state: &mut WorldState, new_head: &str) -> Result<(), ImportError> {
+        let branch = match tree.branch(new_head, chain) {
+            Some(branch) if !branch.is_empty() => branch,
+            _ => return Err(ImportError::UnknownParent { previous_hash: new_head.to_string() }),
+        };
+        let fork_index = branch[0].header.index as usize;
+
+        let mut next_state = WorldState::from_blocks(&chain[..fork_index]).map_err(ImportError::State)?;
+        for block in &branch {
+            if let Err(e) = next_state.apply_block(block) {
+                warn!("Side branch block {} is invalid: {}", block.hash, e);
+                tree.remove_side(&block.hash);
+                return Err(ImportError::State(e));
+            }
+        }
+
+        // Хранилище переключается на новую ветку одной операцией: при ошибке цепочка и состояние не меняются
+        self.store
+            .lock()
+            .await
+            .append_branch(&branch)
+            .map_err(|e| ImportError::Storage(e.to_string()))?;
+
+        let orphaned = chain.split_off(fork_index);
+        info!(
+            "Chain reorganization at height {}: {} blocks replaced by {}",
+            fork_index,
+            orphaned.len(),
+            branch.len()
+        );
+
+        let mut included = HashSet::new();
+        for block in &branch {
+            tree.set_canonical(&block.hash);
+            included.extend(block.transactions.iter().map(|tx| tx.hash.clone()));
+            self.events.emit(ChainEvent::NewBlock(block.clone()));
+        }
+        chain.extend(branch);
+        self.publish_head(chain, tree);
+        *state = next_state;
+
+        let mut mempool = self.mempool.lock().await;
+        for hash in &included {
+            mempool.remove_transaction(hash);
+        }
+        for block in orphaned {
+            for tx in block.transactions.iter().filter(|tx| tx.addr != NETWORK_ADDRESS && !included.contains(&tx.hash)) {
+                if let Err(e) = mempool.add_transaction(tx.clone(), state) {
+                    info!("Orphaned transaction {} dropped: {}", tx.hash, e);
+                    mempool.record_dropped(&tx.hash, e);
+                }
+            }
+            tree.set_side(block);
+        }
+        Ok(())
+    }
+}
+
+// Вес цепочки до блока hash для обмена с узлами. Сетевое сообщение хранит вес в u64
+pub fn head_weight(tree: &BlockTree, hash: &str) -> u64 {
+    u64::try_from(tree.weight(hash)).unwrap_or(u64::MAX)
+}
+
+// Длительность слота узла для тестов
+#[cfg(test)]
+pub const TEST_SLOT_MS: u128 = 1000;
+
+#[cfg(test)]
+impl Blockchain {
+    // Узел для тестов: цепочка из genesis, единственный валидатор validator, свой ключ не участвует в PoS
+    pub fn for_tests(genesis: &Block, validator: &str, mut store: Box<dyn crate::storage::BlockStore>) -> Blockchain {
+        let mut pos = PoS::new(TEST_SLOT_MS);
+        pos.add_participant(validator.to_string(), 100);
+        let chain = vec![genesis.clone()];
+        let tree = BlockTree::new(crate::fork_choice::ForkChoiceRule::LongestChain, &chain, &pos);
+        store.append(genesis).unwrap();
+        let state = WorldState::from_blocks(&chain).unwrap();
+        let (send_to_nodes_link, _) = tokio::sync::mpsc::channel(10);
+        let (head, _) = watch::channel(ChainHead { height: 0, hash: genesis.hash.clone(), weight: head_weight(&tree, &genesis.hash) });
+        Blockchain::new(
+            Arc::new(Mutex::new(Mempool::new())),
+            Arc::new(Mutex::new(chain)),
+            Arc::new(Mutex::new(tree)),
+            Arc::new(Mutex::new(store)),
+            Arc::new(Mutex::new(state)),
+            Arc::new(Mutex::new(pos)),
+            Arc::new(keys::test_keypair(0)),
+            send_to_nodes_link,
+            head,
+        )
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use crate::storage::{BlockStore, MemoryBlockStore, TransactionLocation};
+
+    // Хранилище, которое не может записать ветку из нескольких блоков
+    struct BrokenBranchStore(MemoryBlockStore);
+
+    impl BlockStore for BrokenBranchStore {
+        fn append_branch(&mut self, blocks: &[Block]) -> io::Result<()> {
+            if blocks.len() > 1 {
+                return Err(io::Error::other("disk full"));
+            }
+            self.0.append_branch(blocks)
+        }
+
+        fn get_by_height(&self, height: u64) -> io::Result<Option<Block>> {
+            self.0.get_by_height(height)
+        }
+
+        fn get_by_hash(&self, hash: &str) -> io::Result<Option<Block>> {
+            self.0.get_by_hash(hash)
+        }
+
+        fn transaction_location(&self, hash: &str) -> Option<TransactionLocation> {
+            self.0.transaction_location(hash)
+        }
+
+        fn height(&self) -> Option<u64> {
+            self.0.height()
+        }
+    }
+
+    fn transfer(sender: &Keypair, to: &str, amount: u128, nonce: u64) -> Transaction {
+        let addr = keys::address(&sender.public);
+        let signature = keys::sign(sender, &Transaction::signing_bytes(&addr, to, amount, 0, 1, nonce));
+        Transaction::new(addr, to.to_string(), amount, 1, 0, nonce).with_signature(signature)
+    }
+
+    // Подписанный блок валидатора с наградой, время блока - начало слота
+    fn child(parent: &Block, mut transactions: Vec<Transaction>, validator: &Keypair, slot: u64) -> Block {
+        let address = keys::address(&validator.public);
+        let index = parent.header.index + 1;
+        transactions.push(Transaction::new(NETWORK_ADDRESS.to_string(), address.clone(), BLOCK_REWARD, 0, 0, index));
+        let mut block = Block::new(index, parent.hash.clone(), transactions, address, slot);
+        block.header.timestamp = slot as u128 * TEST_SLOT_MS;
+        block.hash = block.header.calculate_hash();
+        block.sign(validator);
+        block
+    }
+
+    /*
+        Основная цепочка: genesis <- main (alice -> bob).
+        Боковая ветка: genesis <- side <- side_second (dave -> carol), длиннее основной.
+        Возвращает узел после импорта всех блоков, блок main, вершину ветки, транзакцию alice -> bob и результат импорта вершины.
+    */
+    async fn import_fork(store: Box<dyn BlockStore>) -> (Blockchain, Block, Block, Transaction, Result<(), ImportError>) {
+        let (validator, alice, dave) = (keys::test_keypair(1), keys::test_keypair(2), keys::test_keypair(3));
+        let mut balances = BTreeMap::new();
+        balances.insert(keys::address(&alice.public), 100);
+        balances.insert(keys::address(&dave.public), 100);
+        let genesis = Blockchain::create_genesis_block(&balances);
+        let blockchain = Blockchain::for_tests(&genesis, &keys::address(&validator.public), store);
+
+        let to_bob = transfer(&alice, "bob", 10, 0);
+        let main = child(&genesis, vec![to_bob.clone()], &validator, 1);
+        blockchain.import_block(main.clone()).await.unwrap();
+        assert_eq!(blockchain.state.lock().await.balance("bob"), 10);
+
+        let side = child(&genesis, Vec::new(), &validator, 2);
+        blockchain.import_block(side.clone()).await.unwrap();
+        // Ветка той же длины не переключает цепочку
+        assert_eq!(blockchain.best().await, (1, main.hash.clone()));
+
+        let side_second = child(&side, vec![transfer(&dave, "carol", 5, 0)], &validator, 3);
+        let result = blockchain.import_block(side_second.clone()).await;
+        (blockchain, main, side_second, to_bob, result)
+    }
+
+    #[tokio::test]
+    async fn reorganization_switches_state_and_returns_orphaned_transactions() {
+        let (blockchain, main, side_second, to_bob, result) = import_fork(Box::new(MemoryBlockStore::new())).await;
+        result.unwrap();
+
+        assert_eq!(blockchain.best().await, (2, side_second.hash.clone()));
+        {
+            let state = blockchain.state.lock().await;
+            assert_eq!((state.balance("bob"), state.balance("carol")), (0, 5));
+            assert_eq!(state.balance(&to_bob.addr), 100);
+        }
+        assert!(blockchain.mempool.lock().await.contains(&to_bob.hash));
+        assert!(blockchain.knows(&main.hash).await);
+
+        let store = blockchain.store.lock().await;
+        assert!(store.get_by_hash(&main.hash).unwrap().is_none());
+        assert_eq!(store.get_by_height(2).unwrap().unwrap().hash, side_second.hash);
+        assert_eq!(store.transaction_location(&to_bob.hash), None);
+    }
+
+    #[tokio::test]
+    async fn failed_reorganization_keeps_current_chain() {
+        let (blockchain, main, _, to_bob, result) = import_fork(Box::new(BrokenBranchStore(MemoryBlockStore::new()))).await;
+        assert!(matches!(result, Err(ImportError::Storage(_))));
+
+        assert_eq!(blockchain.best().await, (1, main.hash.clone()));
+        assert_eq!(blockchain.state.lock().await.balance("bob"), 10);
+        assert!(!blockchain.mempool.lock().await.contains(&to_bob.hash));
+        let store = blockchain.store.lock().await;
+        assert_eq!(store.height(), Some(1));
+        assert_eq!(store.get_by_hash(&main.hash).unwrap().unwrap().hash, main.hash);
+    }
+}
@@ -0,0 +1,420 @@
+/*
+    Настройки узла.
+    Значения берутся по порядку, каждое следующее переопределяет предыдущее:
+    1. Значения по умолчанию
+    2. Файл настроек (config.json или путь из --config / OXION_CONFIG)
+    3. Переменные окружения OXION_<ИМЯ>, например OXION_P2P_LISTEN
+    4. Флаги командной строки --<имя>, например --p2p-listen 127.0.0.1:31314
+    Списки (bootstrap_peers) в переменных окружения и флагах задаются через запятую.
+*/
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use serde::Deserialize;
+use crate::fork_choice::ForkChoiceRule;
+use crate::storage::StorageKind;
+use crate::transaction::BlockLimits;
+
+// Файл настроек по умолчанию. Если его нет, используются значения по умолчанию
+const DEFAULT_CONFIG_FILE: &str = "config.json";
+// Префикс переменных окружения
+const ENV_PREFIX: &str = "OXION_";
+
+// Настройки, которые можно переопределить переменными окружения и флагами, с описанием для --help
+const OVERRIDES: [(&str, &str); 17] = [
+    ("log_level", "trace, debug, info, warn or error"),
+    ("data_dir", "directory for blocks, peers and bans"),
+    ("storage", "block storage: file or memory"),
+    ("key_file", "validator key file"),
+    ("chain_id", "network identifier"),
+    ("p2p_listen", "address for peer connections"),
+    ("rpc_listen", "address for the RPC server"),
+    ("rpc_max_batch_size", "maximum requests in one JSON-RPC batch"),
+    ("bootstrap_peers", "comma-separated host:port list"),
+    ("target_outbound_peers", "outbound connections to keep, 0 disables dialing"),
+    ("max_frame_size", "maximum network message size in bytes"),
+    ("block_time_ms", "PoS slot duration in milliseconds"),
+    ("fork_choice", "longest_chain or heaviest_stake"),
+    ("max_block_transactions", "maximum transactions per block"),
+    ("max_block_bytes", "maximum size of block transactions in bytes"),
+    ("max_block_fee_weight", "maximum sum of transaction fees per block"),
+    ("empty_blocks", "produce blocks without transactions: true or false"),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    // Каталог для хранения блоков
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    // Тип хранилища блоков: "file" или "memory"
+    #[serde(default)]
+    pub storage: StorageKind,
+    // Начальные балансы аккаунтов, записываемые в генезис-блок
+    #[serde(default)]
+    pub genesis_balances: BTreeMap<String, u128>,
+    // Файл с ключом узла. Ключ подписывает блоки, его открытый ключ - адрес валидатора
+    #[serde(default = "default_key_file")]
+    pub key_file: String,
+    // Набор валидаторов. Если пуст, единственным валидатором становится сам узел
+    #[serde(default)]
+    pub validators: Vec<ValidatorConfig>,
+    // Правило выбора основной цепочки: "longest_chain" или "heaviest_stake"
+    #[serde(default)]
+    pub fork_choice: ForkChoiceRule,
+    // Максимальный размер сетевого сообщения в байтах
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+    // Загрузочные узлы (host:port), к которым узел подключается при старте
+    #[serde(default)]
+    pub bootstrap_peers: Vec<String>,
+    // Сколько исходящих соединений поддерживать. 0 - узел только принимает соединения
+    #[serde(default = "default_target_outbound_peers")]
+    pub target_outbound_peers: usize,
+    // Идентификатор сети. Узлы подключаются только к узлам с тем же идентификатором и генезис-блоком
+    #[serde(default = "default_chain_id")]
+    pub chain_id: String,
+    // Адрес для входящих соединений узлов
+    #[serde(default = "default_p2p_listen")]
+    pub p2p_listen: SocketAddr,
+    // Адрес RPC сервера
+    #[serde(default = "default_rpc_listen")]
+    pub rpc_listen: SocketAddr,
+    // Максимальное количество запросов в одном пакете JSON-RPC
+    #[serde(default = "default_rpc_max_batch_size")]
+    pub rpc_max_batch_size: usize,
+    // Длительность слота PoS (время блока) в миллисекундах
+    #[serde(default = "default_block_time_ms")]
+    pub block_time_ms: u64,
+    // Максимальное количество транзакций в блоке
+    #[serde(default = "default_max_block_transactions")]
+    pub max_block_transactions: usize,
+    // Максимальный размер транзакций блока в байтах
+    #[serde(default = "default_max_block_bytes")]
+    pub max_block_bytes: usize,
+    // Максимальная сумма комиссий транзакций блока. Не задана - без ограничения
+    #[serde(default)]
+    pub max_block_fee_weight: Option<u64>,
+    // Создавать пустые блоки, если мемпул пуст
+    #[serde(default)]
+    pub empty_blocks: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValidatorConfig {
+    // Открытый ключ валидатора в base64
+    pub address: String,
+    pub stake: u64,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read { path: String, error: std::io::Error },
+    Parse { path: String, error: String },
+    InvalidValue { key: String, value: String, reason: String },
+    UnknownFlag(String),
+    MissingValue(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => write!(f, "Failed to read config file {}: {}", path, error),
+            ConfigError::Parse { path, error } => write!(f, "Failed to parse config file {}: {}", path, error),
+            ConfigError::InvalidValue { key, value, reason } => write!(f, "Invalid value {:?} for {}: {}", value, key, reason),
+            ConfigError::UnknownFlag(flag) => write!(f, "Unknown flag {}", flag),
+            ConfigError::MissingValue(flag) => write!(f, "Flag {} requires a value", flag),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn default_log_level() -> String {
+    String::from("info")
+}
+
+fn default_data_dir() -> String {
+    String::from("data")
+}
+
+fn default_key_file() -> String {
+    String::from("node.key")
+}
+
+fn default_chain_id() -> String {
+    String::from("oxion")
+}
+
+fn default_max_frame_size() -> usize {
+    tcp_module::codec::DEFAULT_MAX_FRAME_SIZE
+}
+
+fn default_target_outbound_peers() -> usize {
+    tcp_module::config::DEFAULT_TARGET_OUTBOUND
+}
+
+fn default_p2p_listen() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 31313))
+}
+
+fn default_rpc_listen() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8080))
+}
+
+fn default_rpc_max_batch_size() -> usize {
+    500
+}
+
+fn default_block_time_ms() -> u64 {
+    20000
+}
+
+fn default_max_block_transactions() -> usize {
+    BlockLimits::default().max_transactions
+}
+
+fn default_max_block_bytes() -> usize {
+    BlockLimits::default().max_bytes
+}
+
+// Флаг командной строки для настройки: p2p_listen -> --p2p-listen
+fn flag_name(key: &str) -> String {
+    format!("--{}", key.replace('_', "-"))
+}
+
+// Текст для --help
+pub fn usage() -> String {
+    let mut text = String::from("Usage: hybrid_blockchain [--config <file>] [--<option> <value>]...\n\nOptions:\n");
+    text.push_str(&format!("  {:<26} {}\n", "--config", "config file (default config.json)"));
+    for (key, description) in OVERRIDES.iter() {
+        text.push_str(&format!("  {:<26} {}\n", flag_name(key), description));
+    }
+    text.push_str(&format!("\nEvery option can also be set with the {}<OPTION> environment variable.\n", ENV_PREFIX));
+    text
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value.trim().parse().map_err(|e: T::Err| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+impl Config {
+    // Ограничения размера блока для производителя блоков
+    pub fn block_limits(&self) -> BlockLimits {
+        BlockLimits {
+            max_transactions: self.max_block_transactions,
+            max_bytes: self.max_block_bytes,
+            max_fee_weight: self.max_block_fee_weight,
+        }
+    }
+
+    /*
+        Собирает настройки из файла, переменных окружения env и аргументов командной строки args
+        (без имени программы).
+    */
+    pub fn load<F: Fn(&str) -> Option<String>>(args: &[String], env: F) -> Result<Config, ConfigError> {
+        let flags = Self::parse_flags(args)?;
+
+        let explicit_path = flags
+            .iter()
+            .rev()
+            .find(|(key, _)| key == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| env(&format!("{}CONFIG", ENV_PREFIX)));
+        let mut config = Self::from_file(explicit_path.as_deref())?;
+
+        for (key, _) in OVERRIDES.iter() {
+            if let Some(value) = env(&format!("{}{}", ENV_PREFIX, key.to_uppercase())) {
+                config.set(key, &value)?;
+            }
+        }
+        for (key, value) in flags.iter().filter(|(key, _)| key != "config") {
+            config.set(key, value)?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Проверяет итоговые значения, откуда бы они ни пришли: из файла, окружения или флагов
+    fn validate(&self) -> Result<(), ConfigError> {
+        let positive = [
+            ("block_time_ms", self.block_time_ms),
+            ("max_block_transactions", self.max_block_transactions as u64),
+            ("max_block_bytes", self.max_block_bytes as u64),
+            ("max_frame_size", self.max_frame_size as u64),
+            ("rpc_max_batch_size", self.rpc_max_batch_size as u64),
+        ];
+        for (key, value) in positive {
+            if value == 0 {
+                return Err(ConfigError::InvalidValue {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    reason: String::from("must be positive"),
+                });
+            }
+        }
+        // Полный блок должен помещаться в один кадр сети
+        if self.max_block_bytes > self.max_frame_size {
+            return Err(ConfigError::InvalidValue {
+                key: String::from("max_block_bytes"),
+                value: self.max_block_bytes.to_string(),
+                reason: format!("must not exceed max_frame_size ({})", self.max_frame_size),
+            });
+        }
+        Ok(())
+    }
+
+    // Файл по умолчанию может отсутствовать, явно указанный - обязателен
+    fn from_file(explicit_path: Option<&str>) -> Result<Config, ConfigError> {
+        let path = explicit_path.unwrap_or(DEFAULT_CONFIG_FILE);
+        let data = if explicit_path.is_none() && !Path::new(path).exists() {
+            String::from("{}")
+        } else {
+            fs::read_to_string(path).map_err(|error| ConfigError::Read { path: path.to_string(), error })?
+        };
+        serde_json::from_str(&data).map_err(|e| ConfigError::Parse { path: path.to_string(), error: e.to_string() })
+    }
+
+    // Разбирает флаги вида --name value и --name=value в пары (настройка, значение)
+    fn parse_flags(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+        let mut flags = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let key = flag
+                .strip_prefix("--")
+                .map(|name| name.replace('-', "_"))
+                .filter(|key| key == "config" || OVERRIDES.iter().any(|(name, _)| name == key))
+                .ok_or_else(|| ConfigError::UnknownFlag(arg.clone()))?;
+            let value = match inline_value {
+                Some(value) => value,
+                None => args.next().cloned().ok_or_else(|| ConfigError::MissingValue(flag.to_string()))?,
+            };
+            flags.push((key, value));
+        }
+        Ok(flags)
+    }
+
+    // Устанавливает настройку key из строкового значения
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "log_level" => self.log_level = value.to_string(),
+            "data_dir" => self.data_dir = value.to_string(),
+            "storage" => {
+                self.storage = serde_json::from_value(serde_json::Value::String(value.to_string())).map_err(|e| {
+                    ConfigError::InvalidValue { key: key.to_string(), value: value.to_string(), reason: e.to_string() }
+                })?;
+            }
+            "key_file" => self.key_file = value.to_string(),
+            "chain_id" => self.chain_id = value.to_string(),
+            "p2p_listen" => self.p2p_listen = parse_value(key, value)?,
+            "rpc_listen" => self.rpc_listen = parse_value(key, value)?,
+            "rpc_max_batch_size" => self.rpc_max_batch_size = parse_value(key, value)?,
+            "bootstrap_peers" => {
+                self.bootstrap_peers = value
+                    .split(',')
+                    .map(|peer| peer.trim().to_string())
+                    .filter(|peer| !peer.is_empty())
+                    .collect();
+            }
+            "target_outbound_peers" => self.target_outbound_peers = parse_value(key, value)?,
+            "max_frame_size" => self.max_frame_size = parse_value(key, value)?,
+            "block_time_ms" => self.block_time_ms = parse_value(key, value)?,
+            "max_block_transactions" => self.max_block_transactions = parse_value(key, value)?,
+            "max_block_bytes" => self.max_block_bytes = parse_value(key, value)?,
+            "max_block_fee_weight" => self.max_block_fee_weight = Some(parse_value(key, value)?),
+            "empty_blocks" => self.empty_blocks = parse_value(key, value)?,
+            "fork_choice" => {
+                self.fork_choice = serde_json::from_value(serde_json::Value::String(value.to_string())).map_err(|e| {
+                    ConfigError::InvalidValue { key: key.to_string(), value: value.to_string(), reason: e.to_string() }
+                })?;
+            }
+            _ => return Err(ConfigError::UnknownFlag(flag_name(key))),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn write_config(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("oxion_config_{}_{}.json", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn flags_override_env_and_env_overrides_file() {
+        let path = write_config("overrides", r#"{"data_dir": "file_dir", "rpc_listen": "127.0.0.1:9000", "block_time_ms": 1000}"#);
+        let env: HashMap<String, String> = [
+            ("OXION_CONFIG", path.as_str()),
+            ("OXION_RPC_LISTEN", "127.0.0.1:9001"),
+            ("OXION_BOOTSTRAP_PEERS", "a:1, b:2,"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let config = Config::load(&args(&["--rpc-listen", "127.0.0.1:9002", "--p2p-listen=127.0.0.1:31314"]), |key| env.get(key).cloned()).unwrap();
+        assert_eq!(config.data_dir, "file_dir");
+        assert_eq!(config.block_time_ms, 1000);
+        assert_eq!(config.rpc_listen, "127.0.0.1:9002".parse().unwrap());
+        assert_eq!(config.p2p_listen, "127.0.0.1:31314".parse().unwrap());
+        assert_eq!(config.bootstrap_peers, vec![String::from("a:1"), String::from("b:2")]);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn invalid_flags_are_rejected() {
+        let no_env = |_: &str| None;
+        assert!(matches!(Config::load(&args(&["--unknown", "1"]), no_env), Err(ConfigError::UnknownFlag(_))));
+        assert!(matches!(Config::load(&args(&["--data-dir"]), no_env), Err(ConfigError::MissingValue(_))));
+        assert!(matches!(Config::load(&args(&["--p2p-listen", "nowhere"]), no_env), Err(ConfigError::InvalidValue { .. })));
+        assert!(matches!(Config::load(&args(&["--block-time-ms", "0"]), no_env), Err(ConfigError::InvalidValue { .. })));
+        assert!(matches!(Config::load(&args(&["--storage", "memroy"]), no_env), Err(ConfigError::InvalidValue { .. })));
+        assert!(matches!(Config::load(&args(&["--config", "/nonexistent/config.json"]), no_env), Err(ConfigError::Read { .. })));
+    }
+
+    #[test]
+    fn invalid_file_values_are_rejected() {
+        let cases = [
+            ("zero_block_time", r#"{"block_time_ms": 0}"#),
+            ("zero_block_size", r#"{"max_block_transactions": 0}"#),
+            ("zero_block_bytes", r#"{"max_block_bytes": 0}"#),
+            ("zero_frame_size", r#"{"max_frame_size": 0}"#),
+            ("zero_batch_size", r#"{"rpc_max_batch_size": 0}"#),
+            ("block_over_frame", r#"{"max_block_bytes": 2048, "max_frame_size": 1024}"#),
+        ];
+        for (name, contents) in cases {
+            let path = write_config(name, contents);
+            let result = Config::load(&args(&["--config", &path]), |_: &str| None);
+            assert!(matches!(result, Err(ConfigError::InvalidValue { .. })), "{} accepted", contents);
+            let _ = fs::remove_file(path);
+        }
+
+        let path = write_config("unknown_storage", r#"{"storage": "memroy"}"#);
+        assert!(matches!(Config::load(&args(&["--config", &path]), |_: &str| None), Err(ConfigError::Parse { .. })));
+        let _ = fs::remove_file(path);
+    }
+}
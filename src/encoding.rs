@@ -0,0 +1,142 @@
+/*
+    Каноническое бинарное кодирование для хеширования и подписи.
+    Одни и те же данные всегда кодируются в одни и те же байты, поэтому хеши не зависят от serde_json
+    и могут быть воспроизведены независимыми реализациями.
+
+    Правила кодирования:
+    - u64, u128 - big-endian фиксированной длины (8 и 16 байт)
+    - строка - длина в байтах (u32 big-endian), затем байты UTF-8
+    - кодирование каждой структуры начинается с тега домена (строки), чтобы
+      байты транзакции никогда не совпадали с байтами заголовка блока
+
+    Транзакция: TRANSACTION_DOMAIN, addr, to, amount (u128), timestamp (u128), fee (u64), nonce (u64)
+    Заголовок блока: BLOCK_HEADER_DOMAIN, index (u64), timestamp (u128), previous_hash, transactions_root, nonce (u64), validator, slot (u64)
+    Хеш - sha256 от закодированных байт в нижнем hex. Подпись транзакции ставится на те же байты.
+*/
+use sha2::{Sha256, Digest};
+
+pub const TRANSACTION_DOMAIN: &str = "oxion.transaction.v1";
+pub const BLOCK_HEADER_DOMAIN: &str = "oxion.block_header.v1";
+
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    // Создает кодировщик и записывает тег домена
+    pub fn new(domain: &str) -> Encoder {
+        let mut encoder = Encoder { buf: Vec::new() };
+        encoder.str(domain);
+        encoder
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn u128(&mut self, value: u128) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn str(&mut self, value: &str) -> &mut Self {
+        self.len(value.len());
+        self.buf.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+
+    fn len(&mut self, len: usize) {
+        let len = u32::try_from(len).expect("Encoded value is too long");
+        self.buf.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+// sha256 от байт в виде нижнего hex
+pub fn hash_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHeader;
+    use crate::transaction::Transaction;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn primitives() {
+        let bytes = Encoder::new("d").u64(1).u128(2).str("ab").finish();
+        assert_eq!(
+            hex(&bytes),
+            concat!(
+                "0000000164",
+                "0000000000000001",
+                "00000000000000000000000000000002",
+                "000000026162",
+            )
+        );
+    }
+
+    #[test]
+    fn strings_are_unambiguous() {
+        let ab_c = Encoder::new("d").str("ab").str("c").finish();
+        let a_bc = Encoder::new("d").str("a").str("bc").finish();
+        assert_ne!(ab_c, a_bc);
+    }
+
+    #[test]
+    fn transaction_golden_vector() {
+        let bytes = Transaction::signing_bytes("alice", "bob", 1000, 1700000000000, 5, 7);
+        assert_eq!(
+            hex(&bytes),
+            concat!(
+                "000000146f78696f6e2e7472616e73616374696f6e2e7631",
+                "00000005616c696365",
+                "00000003626f62",
+                "000000000000000000000000000003e8",
+                "00000000000000000000018bcfe56800",
+                "0000000000000005",
+                "0000000000000007",
+            )
+        );
+        assert_eq!(
+            Transaction::new("alice".to_string(), "bob".to_string(), 1000, 5, 1700000000000, 7).hash,
+            "066217396c0dbac41ec4deb8ddd984c53dfa48dc99bacbbb8c7591c808fd4f05"
+        );
+    }
+
+    #[test]
+    fn block_header_golden_vector() {
+        let header = BlockHeader {
+            index: 1,
+            timestamp: 1700000000000,
+            previous_hash: "0".to_string(),
+            transactions_root: "ab".to_string(),
+            nonce: 0,
+            validator: "v".to_string(),
+            slot: 85000000,
+        };
+        assert_eq!(
+            hex(&header.encode()),
+            concat!(
+                "000000156f78696f6e2e626c6f636b5f6865616465722e7631",
+                "0000000000000001",
+                "00000000000000000000018bcfe56800",
+                "0000000130",
+                "000000026162",
+                "0000000000000000",
+                "0000000176",
+                "000000000510ff40",
+            )
+        );
+        assert_eq!(header.calculate_hash(), "cb14de7a94121547dd90be52f52fc173d9045fed9db3a22d3104a1c998756f00");
+    }
+}
@@ -0,0 +1,48 @@
+/*
+    Внутренняя шина событий узла.
+    Блокчейн сообщает о каждом блоке, добавленном в основную цепочку (созданном, полученном от сети
+    или вошедшем в цепочку при смене ветки), мемпул - о каждой новой ожидающей транзакции.
+    Подписчики (WebSocket подписки RPC сервера) получают события через tokio broadcast канал.
+    Если подписчик не успевает читать события, старые события для него теряются.
+*/
+use tokio::sync::broadcast;
+use crate::block::Block;
+use crate::transaction::Transaction;
+
+// Количество событий, которое может накопиться у медленного подписчика
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    // Блок добавлен в основную цепочку
+    NewBlock(Block),
+    // Транзакция добавлена в мемпул
+    PendingTransaction(Transaction),
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ChainEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        EventBus { sender }
+    }
+
+    // Отправляет событие всем подписчикам. Без подписчиков событие отбрасывается
+    pub fn emit(&self, event: ChainEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
@@ -0,0 +1,229 @@
+/*
+    Дерево блоков и правило выбора основной цепочки.
+    Основная цепочка хранится в chain, дерево хранит для каждого известного блока родителя, высоту
+    и накопленный вес ветки, а также тела блоков боковых веток.
+    Если вес боковой ветки становится больше веса основной, узел переключается на нее (реорганизация).
+*/
+use std::collections::HashMap;
+use serde::Deserialize;
+use crate::block::{Block, BlockHeader};
+use crate::pos::PoS;
+
+// Правило выбора основной цепочки
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForkChoiceRule {
+    // Побеждает самая длинная цепочка
+    #[default]
+    LongestChain,
+    // Побеждает цепочка с наибольшей суммой стейка валидаторов, создавших блоки
+    HeaviestStake,
+}
+
+struct TreeEntry {
+    index: u64,
+    // Накопленный вес ветки от генезиса до блока включительно
+    weight: u128,
+}
+
+pub struct BlockTree {
+    rule: ForkChoiceRule,
+    entries: HashMap<String, TreeEntry>,
+    // Блоки, не входящие в основную цепочку
+    side_blocks: HashMap<String, Block>,
+}
+
+impl BlockTree {
+    // Создает дерево из основной цепочки
+    pub fn new(rule: ForkChoiceRule, chain: &[Block], pos: &PoS) -> BlockTree {
+        let mut tree = BlockTree {
+            rule,
+            entries: HashMap::new(),
+            side_blocks: HashMap::new(),
+        };
+        for block in chain {
+            tree.insert_entry(block, pos);
+        }
+        tree
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn weight(&self, hash: &str) -> u128 {
+        self.entries.get(hash).map(|entry| entry.weight).unwrap_or(0)
+    }
+
+    // true, если ветка с вершиной candidate тяжелее ветки с вершиной head. При равенстве остается текущая
+    pub fn is_heavier(&self, candidate: &str, head: &str) -> bool {
+        self.weight(candidate) > self.weight(head)
+    }
+
+    // Вес одного блока по текущему правилу
+    fn block_weight(&self, header: &BlockHeader, pos: &PoS) -> u128 {
+        if header.index == 0 {
+            return 0;
+        }
+        match self.rule {
+            ForkChoiceRule::LongestChain => 1,
+            ForkChoiceRule::HeaviestStake => pos.stake_of(&header.validator) as u128,
+        }
+    }
+
+    fn insert_entry(&mut self, block: &Block, pos: &PoS) {
+        let weight = self.weight(&block.header.previous_hash) + self.block_weight(&block.header, pos);
+        self.entries.insert(block.hash.clone(), TreeEntry { index: block.header.index, weight });
+    }
+
+    // Добавляет блок основной цепочки
+    pub fn insert_canonical(&mut self, block: &Block, pos: &PoS) {
+        self.insert_entry(block, pos);
+    }
+
+    // Добавляет блок боковой ветки
+    pub fn insert_side(&mut self, block: Block, pos: &PoS) {
+        self.insert_entry(&block, pos);
+        self.side_blocks.insert(block.hash.clone(), block);
+    }
+
+    // Находит блок по хешу в основной цепочке или в боковых ветках
+    pub fn get_block(&self, hash: &str, chain: &[Block]) -> Option<Block> {
+        if let Some(block) = self.side_blocks.get(hash) {
+            return Some(block.clone());
+        }
+        let entry = self.entries.get(hash)?;
+        chain
+            .get(entry.index as usize)
+            .filter(|block| block.hash == hash)
+            .cloned()
+    }
+
+    /*
+        Возвращает блоки боковой ветки от общего с основной цепочкой предка (не включая его) до head.
+        None, если ветка не доходит до основной цепочки.
+    */
+    pub fn branch(&self, head: &str, chain: &[Block]) -> Option<Vec<Block>> {
+        let mut branch = Vec::new();
+        let mut cursor = head.to_string();
+        loop {
+            let entry = self.entries.get(&cursor)?;
+            if chain.get(entry.index as usize).map(|block| block.hash == cursor).unwrap_or(false) {
+                break;
+            }
+            let block = self.side_blocks.get(&cursor)?;
+            cursor = block.header.previous_hash.clone();
+            branch.push(block.clone());
+        }
+        branch.reverse();
+        Some(branch)
+    }
+
+    // Переводит блок в основную цепочку: тело блока теперь хранится в chain
+    pub fn set_canonical(&mut self, hash: &str) {
+        self.side_blocks.remove(hash);
+    }
+
+    // Переводит блок основной цепочки в боковую ветку
+    pub fn set_side(&mut self, block: Block) {
+        self.side_blocks.insert(block.hash.clone(), block);
+    }
+
+    // Удаляет блок боковой ветки и всех его потомков
+    pub fn remove_side(&mut self, hash: &str) {
+        let children: Vec<String> = self
+            .side_blocks
+            .values()
+            .filter(|block| block.header.previous_hash == hash)
+            .map(|block| block.hash.clone())
+            .collect();
+        for child in children {
+            self.remove_side(&child);
+        }
+        self.side_blocks.remove(hash);
+        self.entries.remove(hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos() -> PoS {
+        let mut pos = PoS::new(1000);
+        pos.add_participant(String::from("small"), 10);
+        pos.add_participant(String::from("large"), 90);
+        pos
+    }
+
+    fn child(parent: &Block, validator: &str, slot: u64) -> Block {
+        Block::new(parent.header.index + 1, parent.hash.clone(), Vec::new(), validator.to_string(), slot)
+    }
+
+    // Основная цепочка genesis <- first(small) <- second(small), боковая ветка genesis <- side(large)
+    fn tree(rule: ForkChoiceRule) -> (BlockTree, Vec<Block>, Block) {
+        let genesis = Block::new(0, String::from("0"), Vec::new(), String::new(), 0);
+        let first = child(&genesis, "small", 1);
+        let second = child(&first, "small", 2);
+        let side = child(&genesis, "large", 3);
+        let chain = vec![genesis, first, second];
+        let mut tree = BlockTree::new(rule, &chain, &pos());
+        tree.insert_side(side.clone(), &pos());
+        (tree, chain, side)
+    }
+
+    #[test]
+    fn longest_chain_counts_blocks() {
+        let (mut tree, chain, side) = tree(ForkChoiceRule::LongestChain);
+        let head = chain[2].hash.clone();
+        assert_eq!(tree.weight(&head), 2);
+        assert!(!tree.is_heavier(&side.hash, &head));
+
+        let side_second = child(&side, "large", 4);
+        tree.insert_side(side_second.clone(), &pos());
+        // Равный вес не переключает цепочку
+        assert!(!tree.is_heavier(&side_second.hash, &head));
+        let side_third = child(&side_second, "small", 5);
+        tree.insert_side(side_third.clone(), &pos());
+        assert!(tree.is_heavier(&side_third.hash, &head));
+    }
+
+    #[test]
+    fn heaviest_stake_counts_validator_stake() {
+        let (tree, chain, side) = tree(ForkChoiceRule::HeaviestStake);
+        assert_eq!(tree.weight(&chain[2].hash), 20);
+        assert_eq!(tree.weight(&side.hash), 90);
+        assert!(tree.is_heavier(&side.hash, &chain[2].hash));
+    }
+
+    #[test]
+    fn branch_follows_side_blocks_to_main_chain() {
+        let (mut tree, mut chain, side) = tree(ForkChoiceRule::LongestChain);
+        let side_second = child(&side, "large", 4);
+        tree.insert_side(side_second.clone(), &pos());
+
+        let branch: Vec<String> = tree.branch(&side_second.hash, &chain).unwrap().iter().map(|b| b.hash.clone()).collect();
+        assert_eq!(branch, vec![side.hash.clone(), side_second.hash.clone()]);
+        assert!(tree.branch(&chain[2].hash, &chain).unwrap().is_empty());
+        assert!(tree.branch("unknown", &chain).is_none());
+
+        // Переключение на ветку: блоки ветки переходят в chain, старые блоки - в боковые
+        let (orphaned, orphaned_child) = (chain[1].clone(), chain[2].clone());
+        for block in chain.split_off(1) {
+            tree.set_side(block);
+        }
+        for block in [&side, &side_second] {
+            tree.set_canonical(&block.hash);
+            chain.push(block.clone());
+        }
+        assert_eq!(tree.get_block(&side.hash, &chain).unwrap().hash, side.hash);
+        assert_eq!(tree.get_block(&orphaned.hash, &chain).unwrap().hash, orphaned.hash);
+        assert_eq!(tree.branch(&orphaned.hash, &chain).unwrap().len(), 1);
+
+        // Удаление боковой ветки удаляет и потомков
+        tree.remove_side(&orphaned.hash);
+        assert!(!tree.contains(&orphaned.hash) && !tree.contains(&orphaned_child.hash));
+        assert!(tree.get_block(&orphaned.hash, &chain).is_none());
+        assert!(tree.contains(&side_second.hash));
+    }
+}
@@ -0,0 +1,154 @@
+/*
+    Ключ узла ed25519. Используется для подписи блоков, которые создает узел.
+    Файл ключа содержит секретный ключ (32 байта) в base64. Если файла нет, ключ генерируется и сохраняется.
+    Адрес узла - открытый ключ в base64, в том же формате, что и адреса отправителей транзакций.
+    Ключи и подписи из запросов и сообщений других узлов разбираются с проверкой (KeyError).
+*/
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use log::info;
+
+// Ошибки разбора открытого ключа или подписи в base64
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    InvalidPublicKey(String),
+    InvalidSignature(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::InvalidPublicKey(reason) => write!(f, "Invalid ed25519 public key: {}", reason),
+            KeyError::InvalidSignature(reason) => write!(f, "Invalid ed25519 signature: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+// Загружает ключ из файла или создает новый
+pub fn load_or_generate<P: AsRef<Path>>(path: P) -> io::Result<Keypair> {
+    let path = path.as_ref();
+    if path.exists() {
+        let encoded = fs::read_to_string(path)?;
+        let bytes = BASE64
+            .decode(encoded.trim())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        return keypair_from_secret(&bytes);
+    }
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let keypair = keypair_from_secret(&bytes)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(BASE64.encode(bytes).as_bytes())?;
+    info!("Generated new node key {:?}", path);
+
+    Ok(keypair)
+}
+
+fn keypair_from_secret(bytes: &[u8]) -> io::Result<Keypair> {
+    let secret = SecretKey::from_bytes(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+// Детерминированный ключ для тестов
+#[cfg(test)]
+pub fn test_keypair(seed: u8) -> Keypair {
+    keypair_from_secret(&[seed; 32]).unwrap()
+}
+
+// Адрес узла: открытый ключ в base64
+pub fn address(public: &PublicKey) -> String {
+    BASE64.encode(public.as_bytes())
+}
+
+// Подписывает сообщение, подпись возвращается в base64
+pub fn sign(keypair: &Keypair, message: &[u8]) -> String {
+    BASE64.encode(keypair.sign(message).to_bytes())
+}
+
+// Разбирает адрес: открытый ключ ed25519 в base64
+pub fn decode_public_key(address: &str) -> Result<PublicKey, KeyError> {
+    let bytes = BASE64.decode(address).map_err(|e| KeyError::InvalidPublicKey(e.to_string()))?;
+    PublicKey::from_bytes(&bytes).map_err(|e| KeyError::InvalidPublicKey(e.to_string()))
+}
+
+// Разбирает подпись ed25519 в base64
+pub fn decode_signature(signature: &str) -> Result<Signature, KeyError> {
+    let bytes = BASE64.decode(signature).map_err(|e| KeyError::InvalidSignature(e.to_string()))?;
+    Signature::from_bytes(&bytes).map_err(|e| KeyError::InvalidSignature(e.to_string()))
+}
+
+// Проверяет подпись base64 сообщения открытым ключом address
+pub fn verify(address: &str, message: &[u8], signature: &str) -> bool {
+    match (decode_public_key(address), decode_signature(signature)) {
+        (Ok(public_key), Ok(signature)) => public_key.verify(message, &signature).is_ok(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_key_is_private_and_reloaded() {
+        let dir = std::env::temp_dir().join(format!("oxion_keys_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("node.key");
+
+        let generated = load_or_generate(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let reloaded = load_or_generate(&path).unwrap();
+        assert_eq!(address(&generated.public), address(&reloaded.public));
+
+        fs::write(&path, "not base64").unwrap();
+        assert!(load_or_generate(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn signatures_round_trip() {
+        let keypair = test_keypair(1);
+        let address = address(&keypair.public);
+        let signature = sign(&keypair, b"message");
+        assert!(verify(&address, b"message", &signature));
+        assert!(!verify(&address, b"other message", &signature));
+        assert!(!verify(&super::address(&test_keypair(2).public), b"message", &signature));
+        assert!(!verify(&address, b"message", "garbage"));
+    }
+
+    #[test]
+    fn malformed_keys_and_signatures_are_errors() {
+        assert!(matches!(decode_public_key("not base64!"), Err(KeyError::InvalidPublicKey(_))));
+        assert!(matches!(decode_public_key(&BASE64.encode([1u8; 5])), Err(KeyError::InvalidPublicKey(_))));
+        assert!(decode_public_key(&address(&test_keypair(1).public)).is_ok());
+
+        assert!(matches!(decode_signature("not base64!"), Err(KeyError::InvalidSignature(_))));
+        assert!(matches!(decode_signature(&BASE64.encode([1u8; 10])), Err(KeyError::InvalidSignature(_))));
+        assert!(decode_signature(&sign(&test_keypair(1), b"message")).is_ok());
+    }
+}
@@ -11,42 +11,64 @@ mod transaction;
 mod middleware;
 mod node;
 mod server;
+mod rpc;
 mod consensys;
+mod storage;
+mod state;
+mod encoding;
+mod merkle;
+mod keys;
+mod validation;
+mod fork_choice;
+mod sync;
+mod network;
+mod config;
+mod events;
+mod subscriptions;
+mod amount;
 
 use pos::PoS;
 use std::sync::Arc;
-use std::fs;
-use log::LevelFilter;
-use serde::Deserialize;
+use std::env;
+use std::path::Path;
+use log::{error, info, LevelFilter};
 
-use crossbeam::channel;
-use std::collections::BinaryHeap;
 use tokio::sync::Mutex;
-use std::collections::HashSet;
 use tokio::sync::mpsc;
+use tokio::sync::watch;
 use crate::transaction::Mempool;
-use crate::blockchain::Blockchain;
-
-#[derive(Deserialize)]
-struct Config {
-    log_level: String,
-}
+use crate::blockchain::{Blockchain, ProducerConfig};
+use crate::storage::{BlockStore, FileBlockStore, MemoryBlockStore, StorageKind};
+use crate::state::WorldState;
+use crate::fork_choice::BlockTree;
+use crate::sync::SyncManager;
+use crate::network::NetworkHandler;
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::server::{RPCServer, RpcSettings};
+use tcp_module::config::NetworkConfig;
+use tcp_module::hello::ChainHead;
+use tcp_module::reputation::Reputation;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let config: Config = {
-        let config_str = fs::read_to_string("config.json").expect("Failed to read config file");
-        serde_json::from_str(&config_str).expect("Failed to parse config file")
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", config::usage());
+        return Ok(());
+    }
+    let config = match Config::load(&args, |key| env::var(key).ok()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprint!("{}", config::usage());
+            std::process::exit(2);
+        }
     };
 
     let (tx, rx) = mpsc::channel(10);
-
-    // Очередь для отправки полученных сообщений через RPC на другие узлы.
-    // let (sender_rpc, _receiver_rpc) = channel::unbounded();
-    // Запускает TCP Server и TCP Connect. Управляется TCP Manager.
-    let _tcp_server = tokio::spawn(async {
-        let _ = tcp_module::module::activate(rx).await;
-    });
+    // События соединений и сообщения, полученные от других узлов
+    let (events_tx, events_rx) = mpsc::channel(100);
 
     let log_level = match config.log_level.to_lowercase().as_str() {
         "trace" => LevelFilter::Trace,
@@ -63,26 +85,106 @@ async fn main() -> std::io::Result<()> {
         .filter_module("actix_web", LevelFilter::Off)
         .init();
 
-    // let (sender, _receiver) = channel::unbounded();
+    let keypair = Arc::new(keys::load_or_generate(&config.key_file).expect("Failed to load node key"));
+    info!("Node address: {}", keys::address(&keypair.public));
 
-    let pos = Arc::new(Mutex::new(PoS::new()));
+    let pos = Arc::new(Mutex::new(PoS::new(config.block_time_ms as u128)));
+    {
+        let mut pos = pos.lock().await;
+        if config.validators.is_empty() {
+            pos.add_participant(keys::address(&keypair.public), 100);
+        }
+        for validator in &config.validators {
+            pos.add_participant(validator.address.clone(), validator.stake);
+        }
+    }
 
-    let mut mempool = Arc::new(Mutex::new(Mempool::new()));
+    // Шина событий для WebSocket подписок RPC сервера
+    let event_bus = EventBus::new();
+    let mempool = Arc::new(Mutex::new(Mempool::new().with_events(event_bus.clone())));
 
-    let chain_vector = Arc::new(Mutex::new(vec!(Blockchain::create_genesis_block())));
+    // Открываем хранилище блоков и загружаем из него цепочку
+    let mut store: Box<dyn BlockStore> = match config.storage {
+        StorageKind::Memory => Box::new(MemoryBlockStore::new()),
+        StorageKind::File => Box::new(FileBlockStore::open(&config.data_dir).expect("Failed to open block store")),
+    };
+    if store.height().is_none() {
+        store.append(&Blockchain::create_genesis_block(&config.genesis_balances)).expect("Failed to write genesis block");
+    }
+    let blocks = store.blocks().expect("Failed to load blocks from store");
+
+    // Восстанавливаем балансы аккаунтов из сохраненной цепочки
+    // Без состояния узел не может проверять и создавать блоки, поэтому при ошибке не запускается
+    let state = match WorldState::from_blocks(&blocks) {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Failed to rebuild state from stored blocks: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let state = Arc::new(Mutex::new(state));
 
-    let mut blockchain = Blockchain::new(Arc::clone(&mempool), Arc::clone(&chain_vector));
-    let _blockchain = tokio::spawn(async move {
-        let _ = blockchain.start_thread().await;
+    let tree = Arc::new(Mutex::new(BlockTree::new(config.fork_choice, &blocks, &*pos.lock().await)));
+
+    // Вершина цепочки для приветствия Hello, обновляется блокчейном
+    let last = blocks.last().expect("Blockchain should have at least one block");
+    let weight = blockchain::head_weight(&*tree.lock().await, &last.hash);
+    let (head_tx, head_rx) = watch::channel(ChainHead { height: last.header.index, hash: last.hash.clone(), weight });
+
+    // Счета и блокировки узлов, блокировки сохраняются между запусками
+    let reputation = Reputation::new(Some(Path::new(&config.data_dir).join("bans.json")));
+
+    // Запускает TCP Server и TCP Connect. Управляется TCP Manager.
+    let network_config = NetworkConfig {
+        identity: Arc::clone(&keypair),
+        max_frame_size: config.max_frame_size,
+        bootstrap_peers: config.bootstrap_peers.clone(),
+        target_outbound: config.target_outbound_peers,
+        peers_file: Some(Path::new(&config.data_dir).join("peers.json")),
+        chain_id: config.chain_id.clone(),
+        genesis_hash: blocks[0].hash.clone(),
+        listen_address: config.p2p_listen,
+        head: head_rx,
+        reputation: reputation.clone(),
+    };
+    let _tcp_server = tokio::spawn(async move {
+        let _ = tcp_module::module::activate(rx, events_tx, network_config).await;
     });
 
-    {
-        let mut pos = pos.lock().await;
-        pos.add_participant(String::from("Alice"), 100);
-        pos.add_participant(String::from("Bob"), 50);
+    let chain_vector = Arc::new(Mutex::new(blocks));
+    let store = Arc::new(Mutex::new(store));
+
+    let mut blockchain = Blockchain::new(
+        Arc::clone(&mempool),
+        Arc::clone(&chain_vector),
+        Arc::clone(&tree),
+        Arc::clone(&store),
+        Arc::clone(&state),
+        Arc::clone(&pos),
+        Arc::clone(&keypair),
+        tx.clone(),
+        head_tx,
+    )
+    .with_producer(ProducerConfig { limits: config.block_limits(), empty_blocks: config.empty_blocks })
+    .with_events(event_bus.clone());
+    // Поврежденная цепочка не используется для синхронизации, RPC и создания блоков
+    if !blockchain.is_valid().await {
+        error!("Stored blockchain is invalid");
+        std::process::exit(1);
     }
+    let sync_manager = SyncManager::new(blockchain.clone(), reputation.clone()).await;
+    let mut network_handler = NetworkHandler::new(blockchain.clone(), Arc::clone(&mempool), Arc::clone(&state), sync_manager, reputation.clone(), tx.clone());
+    let _network = tokio::spawn(async move {
+        network_handler.start_thread(events_rx).await;
+    });
+
+    let _blockchain = tokio::spawn(async move {
+        let _ = blockchain.start_thread().await;
+    });
 
-    let _ = server::start_rpc_server(Arc::clone(&mempool), tx, Arc::clone(&chain_vector)).await;
+    let rpc_server = RPCServer::new(Arc::clone(&mempool), tx, Arc::clone(&store), Arc::clone(&state), reputation);
+    let rpc_settings = RpcSettings { listen: config.rpc_listen, max_batch_size: config.rpc_max_batch_size };
+    let _ = server::start_rpc_server(rpc_settings, rpc_server, event_bus).await;
 
     Ok(())
 }
@@ -0,0 +1,165 @@
+/*
+    Дерево Меркла для транзакций блока.
+    Корень дерева записывается в заголовок блока, поэтому включение транзакции в блок
+    можно доказать одним заголовком и доказательством, не скачивая тело блока.
+
+    Лист: sha256(0x00 || хеш транзакции в hex)
+    Узел: sha256(0x01 || левый || правый)
+    Если на уровне нечетное количество узлов, последний поднимается на следующий уровень без изменений.
+    Корень пустого дерева: sha256 от пустой строки.
+*/
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+type Hash = [u8; 32];
+
+// Сторона, с которой соседний узел присоединяется к текущему
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub hash: String,
+    pub side: Side,
+}
+
+// Доказательство включения транзакции: путь от листа до корня
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub steps: Vec<ProofStep>,
+}
+
+fn leaf_hash(tx_hash: &str) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(tx_hash.as_bytes());
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn to_hex(hash: &Hash) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Hash> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+// Строит следующий уровень дерева
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// Корень дерева Меркла для списка хешей транзакций
+pub fn merkle_root<S: AsRef<str>>(tx_hashes: &[S]) -> String {
+    if tx_hashes.is_empty() {
+        return to_hex(&Sha256::digest([]).into());
+    }
+    let mut level: Vec<Hash> = tx_hashes.iter().map(|hash| leaf_hash(hash.as_ref())).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    to_hex(&level[0])
+}
+
+// Строит доказательство включения транзакции с хешем tx_hash. None, если транзакции нет в списке
+pub fn build_proof<S: AsRef<str>>(tx_hashes: &[S], tx_hash: &str) -> Option<MerkleProof> {
+    let index = tx_hashes.iter().position(|hash| hash.as_ref() == tx_hash)?;
+    let mut level: Vec<Hash> = tx_hashes.iter().map(|hash| leaf_hash(hash.as_ref())).collect();
+    let mut position = index;
+    let mut steps = Vec::new();
+
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if let Some(hash) = level.get(sibling) {
+            let side = if sibling < position { Side::Left } else { Side::Right };
+            steps.push(ProofStep { hash: to_hex(hash), side });
+        }
+        level = next_level(&level);
+        position /= 2;
+    }
+
+    Some(MerkleProof { index, steps })
+}
+
+// Проверяет доказательство по корню из заголовка блока. Тело блока не требуется
+pub fn verify_proof(tx_hash: &str, proof: &MerkleProof, root: &str) -> bool {
+    let mut current = leaf_hash(tx_hash);
+    for step in &proof.steps {
+        let sibling = match from_hex(&step.hash) {
+            Some(hash) => hash,
+            None => return false,
+        };
+        current = match step.side {
+            Side::Left => node_hash(&sibling, &current),
+            Side::Right => node_hash(&current, &sibling),
+        };
+    }
+    to_hex(&current) == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("tx{}", i)).collect()
+    }
+
+    #[test]
+    fn root_golden_vector() {
+        assert_eq!(merkle_root(&hashes(3)), "e52026eebb267b65f2d684eb8bea5aefc48d0224008bae3108ff4d29ccdd189e");
+        assert_eq!(merkle_root::<&str>(&[]), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn proofs_verify_for_every_leaf() {
+        for count in 1..=9 {
+            let hashes = hashes(count);
+            let root = merkle_root(&hashes);
+            for hash in &hashes {
+                let proof = build_proof(&hashes, hash).unwrap();
+                assert!(verify_proof(hash, &proof, &root), "count {} hash {}", count, hash);
+            }
+        }
+    }
+
+    #[test]
+    fn proof_rejects_other_transaction_and_root() {
+        let hashes = hashes(5);
+        let root = merkle_root(&hashes);
+        let proof = build_proof(&hashes, "tx2").unwrap();
+        assert!(!verify_proof("tx3", &proof, &root));
+        assert!(!verify_proof("tx2", &proof, &merkle_root(&hashes[..4])));
+        assert!(build_proof(&hashes, "missing").is_none());
+    }
+}
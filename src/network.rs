@@ -0,0 +1,228 @@
+/*
+    Обработка сообщений, полученных от других узлов.
+    Транзакции проверяются и добавляются в мемпул, блоки передаются в импорт блоков.
+    События подключения и сообщения синхронизации цепочки обрабатывает SyncManager.
+    Узлы, присылающие неверные транзакции и блоки, штрафуются.
+    Транзакции и блоки пересылаются другим узлам только после проверки, чтобы узел не распространял
+    чужие неверные данные и не получал за них штрафы от соседей.
+*/
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::interval;
+use log::{debug, info, warn};
+use tcp_module::event::NetworkEvent;
+use tcp_module::message::{Message, MessageType};
+use tcp_module::reputation::{Misbehavior, Reputation};
+use crate::blockchain::Blockchain;
+use crate::state::WorldState;
+use crate::sync::SyncManager;
+use crate::transaction::{Mempool, MempoolError, Transaction};
+use crate::validation::ImportError;
+
+// Как часто проверяется, не завис ли узел синхронизации
+const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct NetworkHandler {
+    blockchain: Blockchain,
+    mempool: Arc<Mutex<Mempool>>,
+    state: Arc<Mutex<WorldState>>,
+    sync: SyncManager,
+    reputation: Reputation,
+    // Очередь рассылки сообщений другим узлам
+    send_to_nodes_link: Sender<Message>,
+}
+
+// Ошибки импорта, в которых виноват приславший блок узел. Устаревший или опережающий блок нарушением не считается
+pub fn block_misbehavior(error: &ImportError) -> Option<Misbehavior> {
+    match error {
+        ImportError::Malformed(_) => Some(Misbehavior::MalformedMessage),
+        ImportError::AlreadyKnown
+        | ImportError::UnknownParent { .. }
+        | ImportError::InvalidTimestamp(_)
+        | ImportError::Storage(_) => None,
+        _ => Some(Misbehavior::InvalidBlock),
+    }
+}
+
+impl NetworkHandler {
+    pub fn new(
+        blockchain: Blockchain,
+        mempool: Arc<Mutex<Mempool>>,
+        state: Arc<Mutex<WorldState>>,
+        sync: SyncManager,
+        reputation: Reputation,
+        send_to_nodes_link: Sender<Message>,
+    ) -> NetworkHandler {
+        NetworkHandler {
+            blockchain,
+            mempool,
+            state,
+            sync,
+            reputation,
+            send_to_nodes_link,
+        }
+    }
+
+    // Обрабатывает события сетевого модуля. Требует запуска в отдельном потоке
+    pub async fn start_thread(&mut self, mut events: Receiver<NetworkEvent>) {
+        info!("Network handler started.");
+
+        let mut sync_timer = interval(SYNC_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => self.handle_event(event).await,
+                    None => break,
+                },
+                _ = sync_timer.tick() => self.sync.check_timeout(Instant::now()).await,
+            }
+        }
+    }
+
+    async fn handle_event(&mut self, event: NetworkEvent) {
+        match event {
+            NetworkEvent::Message { peer, message } => match message.message_type {
+                MessageType::Transaction => self.handle_transaction(peer, &message).await,
+                MessageType::Block => self.handle_block(peer, &message).await,
+                _ => self.sync.handle_event(NetworkEvent::Message { peer, message }).await,
+            },
+            event => self.sync.handle_event(event).await,
+        }
+    }
+
+    // Транзакция от другого узла проходит те же проверки, что и транзакция, полученная через RPC
+    async fn handle_transaction(&self, peer: SocketAddr, message: &Message) {
+        let tx: Transaction = match message.payload() {
+            Ok(tx) => tx,
+            Err(e) => {
+                warn!("Transaction from {} rejected: {}", peer, e);
+                self.reputation.penalize(peer, Misbehavior::from(&e)).await;
+                return;
+            }
+        };
+        // Подпись проверяется до захвата блокировок состояния и мемпула
+        if !tx.verify() {
+            warn!("Transaction {} from {} has an invalid signature", tx.hash, peer);
+            self.reputation.penalize(peer, Misbehavior::InvalidTransaction).await;
+            return;
+        }
+
+        let state = self.state.lock().await;
+        let mut mempool = self.mempool.lock().await;
+        let hash = tx.hash.clone();
+        match mempool.add_transaction(tx, &state) {
+            Ok(()) => {
+                info!("Transaction {} received from {}", hash, peer);
+                self.relay(message);
+            }
+            Err(MempoolError::Duplicate) => debug!("Transaction {} already in mempool", hash),
+            Err(e) => info!("Transaction {} from {} rejected: {}", hash, peer, e),
+        }
+    }
+
+    // Пересылает проверенную транзакцию или блок другим узлам, пока не исчерпан лимит пересылок
+    fn relay(&self, message: &Message) {
+        if message.ttl == 0 {
+            return;
+        }
+        let mut relay = message.clone();
+        relay.ttl -= 1;
+        if let Err(e) = self.send_to_nodes_link.try_send(relay) {
+            warn!("Failed to relay {:?} {}: {}", message.message_type, message.hash, e);
+        }
+    }
+
+    async fn handle_block(&mut self, peer: SocketAddr, message: &Message) {
+        match self.blockchain.import_message(message).await {
+            Ok(()) => self.relay(message),
+            Err(ImportError::AlreadyKnown) => debug!("Block from {} already known", peer),
+            // Узел впереди нас больше чем на один блок: недостающие блоки загружаются синхронизацией
+            Err(ImportError::UnknownParent { .. }) => {
+                if let Some(index) = message.data.get("header").and_then(|header| header.get("index")).and_then(|index| index.as_u64()) {
+                    self.sync.note_peer_height(peer, index).await;
+                }
+            }
+            Err(e) => {
+                warn!("Block from {} rejected: {}", peer, e);
+                if let Some(misbehavior) = block_misbehavior(&e) {
+                    self.reputation.penalize(peer, misbehavior).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use ed25519_dalek::Keypair;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+    use crate::keys;
+    use crate::storage::MemoryBlockStore;
+
+    fn transfer(sender: &Keypair, amount: u128, nonce: u64) -> Transaction {
+        let addr = keys::address(&sender.public);
+        let signature = keys::sign(sender, &Transaction::signing_bytes(&addr, "bob", amount, 0, 1, nonce));
+        Transaction::new(addr, String::from("bob"), amount, 1, 0, nonce).with_signature(signature)
+    }
+
+    #[tokio::test]
+    async fn only_accepted_messages_are_relayed() {
+        let alice = keys::test_keypair(1);
+        let balances = BTreeMap::from([(keys::address(&alice.public), 100)]);
+        let genesis = Blockchain::create_genesis_block(&balances);
+        let blockchain = Blockchain::for_tests(&genesis, "validator", Box::new(MemoryBlockStore::new()));
+        let state = Arc::new(Mutex::new(WorldState::from_blocks(std::slice::from_ref(&genesis)).unwrap()));
+        let reputation = Reputation::new(None);
+        let peer: SocketAddr = "127.0.0.1:30000".parse().unwrap();
+        reputation.connect(peer, "peer-node").await;
+        let sync = SyncManager::new(blockchain.clone(), reputation.clone()).await;
+        let (sender, mut outbox) = mpsc::channel(10);
+        let mut handler = NetworkHandler::new(blockchain, Arc::new(Mutex::new(Mempool::new())), state, sync, reputation, sender);
+
+        let mut forged = transfer(&alice, 10, 0);
+        forged.amount = 90;
+        let invalid = [
+            Message::new(MessageType::Transaction, serde_json::to_value(&forged).unwrap()),
+            Message::new(MessageType::Block, json!({"header": "garbage"})),
+        ];
+        for message in invalid {
+            handler.handle_event(NetworkEvent::Message { peer, message }).await;
+            assert!(outbox.try_recv().is_err());
+        }
+
+        let valid = Message::new(MessageType::Transaction, serde_json::to_value(transfer(&alice, 10, 0)).unwrap());
+        handler.handle_event(NetworkEvent::Message { peer, message: valid.clone() }).await;
+        let relayed = outbox.try_recv().unwrap();
+        assert_eq!((relayed.hash, relayed.ttl), (valid.hash, valid.ttl - 1));
+    }
+
+    #[test]
+    fn only_peer_faults_are_penalized() {
+        let not_penalized = [
+            ImportError::AlreadyKnown,
+            ImportError::UnknownParent { previous_hash: String::from("parent") },
+            ImportError::InvalidTimestamp(String::from("block is from the future")),
+            ImportError::Storage(String::from("disk full")),
+        ];
+        for error in &not_penalized {
+            assert_eq!(block_misbehavior(error), None, "{}", error);
+        }
+
+        assert_eq!(block_misbehavior(&ImportError::Malformed(String::from("bad json"))), Some(Misbehavior::MalformedMessage));
+        let invalid = [
+            ImportError::InvalidHash,
+            ImportError::InvalidSignature,
+            ImportError::InvalidSlot,
+            ImportError::WrongLeader { validator: String::from("mallory") },
+        ];
+        for error in &invalid {
+            assert_eq!(block_misbehavior(error), Some(Misbehavior::InvalidBlock), "{}", error);
+        }
+    }
+}
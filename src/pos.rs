@@ -1,5 +1,14 @@
 // src/pos.rs
-use rand::Rng;
+/*
+    Proof of Stake. Время разбито на слоты фиксированной длины, в каждом слоте блок может создать только один валидатор.
+    Лидер слота выбирается детерминированно: sha256(хеш предыдущего блока, номер слота) по модулю общего стейка,
+    поэтому каждый узел может независимо проверить, что блок создан правильным валидатором.
+*/
+use sha2::{Sha256, Digest};
+use crate::block::BlockHeader;
+use crate::encoding::Encoder;
+
+const LEADER_DOMAIN: &str = "oxion.leader.v1";
 
 pub struct Participant {
     pub address: String,
@@ -7,26 +16,49 @@ pub struct Participant {
 }
 
 pub struct PoS {
+    // Участники отсортированы по адресу, чтобы выбор лидера не зависел от порядка добавления
     pub participants: Vec<Participant>,
+    // Длительность слота в миллисекундах
+    pub slot_duration: u128,
 }
 
 impl PoS {
-    pub fn new() -> Self {
-        PoS { participants: Vec::new() }
+    // Длительность слота должна быть положительной: на нее делится время при расчете номера слота
+    pub fn new(slot_duration: u128) -> Self {
+        assert!(slot_duration > 0, "PoS slot duration must be positive");
+        PoS { participants: Vec::new(), slot_duration }
     }
 
     pub fn add_participant(&mut self, address: String, stake: u64) {
-        self.participants.push(Participant { address, stake });
+        match self.participants.binary_search_by(|p| p.address.cmp(&address)) {
+            Ok(position) => self.participants[position].stake = stake,
+            Err(position) => self.participants.insert(position, Participant { address, stake }),
+        }
+    }
+
+    pub fn stake_of(&self, address: &str) -> u64 {
+        self.participants
+            .binary_search_by(|p| p.address.as_str().cmp(address))
+            .map(|position| self.participants[position].stake)
+            .unwrap_or(0)
     }
 
-    pub fn select_validator(&self) -> Option<&Participant> {
-        if self.participants.is_empty() {
+    // Номер слота, к которому относится временная метка
+    pub fn slot_at(&self, timestamp: u128) -> u64 {
+        (timestamp / self.slot_duration) as u64
+    }
+
+    // Выбирает лидера слота. seed - хеш предыдущего блока
+    pub fn select_validator(&self, seed: &str, slot: u64) -> Option<&Participant> {
+        let total_stake: u64 = self.participants.iter().map(|p| p.stake).sum();
+        if total_stake == 0 {
             return None;
         }
 
-        let total_stake: u64 = self.participants.iter().map(|p| p.stake).sum();
-        let mut rng = rand::thread_rng();
-        let mut rand_stake = rng.gen_range(0..total_stake);
+        let digest = Sha256::digest(Encoder::new(LEADER_DOMAIN).str(seed).u64(slot).finish());
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        let mut rand_stake = u64::from_be_bytes(bytes) % total_stake;
 
         for participant in &self.participants {
             if rand_stake < participant.stake {
@@ -37,4 +69,77 @@ impl PoS {
 
         None
     }
+
+    // Проверяет, что заголовок создан лидером своего слота и метка времени лежит внутри слота
+    pub fn verify_leader(&self, header: &BlockHeader, previous_hash: &str) -> bool {
+        if self.slot_at(header.timestamp) != header.slot {
+            return false;
+        }
+        match self.select_validator(previous_hash, header.slot) {
+            Some(leader) => leader.address == header.validator,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+
+    fn pos(participants: &[(&str, u64)]) -> PoS {
+        let mut pos = PoS::new(1000);
+        for (address, stake) in participants {
+            pos.add_participant(address.to_string(), *stake);
+        }
+        pos
+    }
+
+    fn leader(pos: &PoS, seed: &str, slot: u64) -> String {
+        pos.select_validator(seed, slot).unwrap().address.clone()
+    }
+
+    #[test]
+    fn leader_is_deterministic() {
+        let forward = pos(&[("alice", 10), ("bob", 20), ("carol", 30)]);
+        let reversed = pos(&[("carol", 30), ("bob", 20), ("alice", 10)]);
+        // Расписание зафиксировано: его изменение делает несовместимыми узлы разных версий
+        let leaders: Vec<String> = (0..8).map(|slot| leader(&forward, "seed", slot)).collect();
+        assert_eq!(leaders, ["carol", "alice", "carol", "alice", "bob", "alice", "carol", "carol"]);
+        for slot in 0..100 {
+            assert_eq!(leader(&forward, "seed", slot), leader(&reversed, "seed", slot));
+        }
+        assert!(pos(&[]).select_validator("seed", 0).is_none());
+        assert!(pos(&[("alice", 0)]).select_validator("seed", 0).is_none());
+    }
+
+    #[test]
+    fn leaders_follow_stake() {
+        let pos = pos(&[("small", 10), ("large", 90)]);
+        let large = (0..1000).filter(|&slot| leader(&pos, "seed", slot) == "large").count();
+        assert!((850..950).contains(&large), "large stake led {} of 1000 slots", large);
+    }
+
+    #[test]
+    fn only_slot_leader_is_accepted() {
+        let pos = pos(&[("alice", 50), ("bob", 50)]);
+        let slot = (0..).find(|&slot| leader(&pos, "parent", slot) == "alice").unwrap();
+        let mut header = Block::new(1, String::from("parent"), Vec::new(), String::from("alice"), slot).header;
+        header.timestamp = slot as u128 * 1000 + 1;
+        assert!(pos.verify_leader(&header, "parent"));
+
+        let mut other = header.clone();
+        other.validator = String::from("bob");
+        assert!(!pos.verify_leader(&other, "parent"));
+
+        let mut late = header.clone();
+        late.timestamp += 1000;
+        assert!(!pos.verify_leader(&late, "parent"));
+    }
+
+    #[test]
+    #[should_panic(expected = "slot duration must be positive")]
+    fn zero_slot_duration_is_rejected() {
+        PoS::new(0);
+    }
 }
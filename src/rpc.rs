@@ -0,0 +1,397 @@
+/*
+    Протокол JSON-RPC 2.0: запросы, ответы и коды ошибок.
+    Стандартные коды ошибок определены спецификацией JSON-RPC 2.0,
+    коды приложения лежат в диапазоне -32000..-32099, отведенном спецификацией для ошибок сервера.
+    Параметры методов разбираются в типизированные структуры: их можно передать массивом по порядку полей
+    или объектом с именами полей.
+    Запрос без id - уведомление: метод выполняется, но ответ не отправляется.
+    Тело запроса и параметры разбираются только с проверкой: любые некорректные данные дают ошибку JSON-RPC.
+*/
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::{Number, Value};
+use crate::keys::KeyError;
+use crate::state::StateError;
+use crate::transaction::MempoolError;
+
+// Стандартные ошибки JSON-RPC 2.0
+// Тело запроса не является корректным JSON
+pub const PARSE_ERROR: i32 = -32700;
+// JSON не является корректным запросом
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
+
+// Ошибки приложения
+// У отправителя недостаточно средств для суммы и комиссии
+pub const INSUFFICIENT_FUNDS: i32 = -32001;
+// Nonce уже исполнен или занят транзакцией в мемпуле
+pub const BAD_NONCE: i32 = -32002;
+// Транзакция уже есть в мемпуле
+pub const DUPLICATE_TRANSACTION: i32 = -32003;
+// Блок не найден
+pub const UNKNOWN_BLOCK: i32 = -32004;
+// Подпись транзакции не соответствует отправителю
+pub const INVALID_SIGNATURE: i32 = -32005;
+// Транзакция не найдена
+pub const UNKNOWN_TRANSACTION: i32 = -32006;
+// Достигнуто максимальное количество подписок WebSocket соединения
+pub const SUBSCRIPTION_LIMIT: i32 = -32007;
+// Транзакция отклонена по другой причине
+pub const TRANSACTION_REJECTED: i32 = -32010;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+// Максимальное количество блоков в одном ответе getBlockRange
+pub const MAX_BLOCK_RANGE: u64 = 100;
+// Максимальное количество транзакций в одном ответе getPendingTransactions
+pub const MAX_PENDING_PAGE: usize = 100;
+
+#[derive(Debug, Serialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i32, message: impl Into<String>) -> RpcError {
+        RpcError { code, message: message.into(), data: None }
+    }
+
+    pub fn parse_error(reason: impl fmt::Display) -> RpcError {
+        RpcError::new(PARSE_ERROR, format!("Parse error: {}", reason))
+    }
+
+    pub fn invalid_request(reason: impl fmt::Display) -> RpcError {
+        RpcError::new(INVALID_REQUEST, format!("Invalid request: {}", reason))
+    }
+
+    pub fn method_not_found(method: &str) -> RpcError {
+        RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))
+    }
+
+    pub fn invalid_params(reason: impl fmt::Display) -> RpcError {
+        RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", reason))
+    }
+
+    pub fn internal(reason: impl fmt::Display) -> RpcError {
+        RpcError::new(INTERNAL_ERROR, format!("Internal error: {}", reason))
+    }
+}
+
+impl From<StateError> for RpcError {
+    fn from(e: StateError) -> Self {
+        let code = match e {
+            StateError::InsufficientFunds { .. } => INSUFFICIENT_FUNDS,
+            StateError::NonceTooLow { .. } | StateError::NonceTooHigh { .. } => BAD_NONCE,
+            StateError::InvalidReward(_) | StateError::Overflow => TRANSACTION_REJECTED,
+        };
+        RpcError::new(code, e.to_string())
+    }
+}
+
+impl From<KeyError> for RpcError {
+    fn from(e: KeyError) -> Self {
+        RpcError::invalid_params(e)
+    }
+}
+
+impl From<MempoolError> for RpcError {
+    fn from(e: MempoolError) -> Self {
+        match e {
+            MempoolError::Duplicate => RpcError::new(DUPLICATE_TRANSACTION, e.to_string()),
+            MempoolError::NonceInUse => RpcError::new(BAD_NONCE, e.to_string()),
+            MempoolError::State(e) => e.into(),
+        }
+    }
+}
+
+// Разбирает тело запроса: любые байты, не являющиеся JSON (в том числе не UTF-8), дают Parse error
+pub fn parse_json(bytes: &[u8]) -> Result<Value, RpcError> {
+    serde_json::from_slice(bytes).map_err(RpcError::parse_error)
+}
+
+// Идентификатор запроса: число, строка или null
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum RpcId {
+    Number(Number),
+    String(String),
+    Null,
+}
+
+#[derive(Debug)]
+pub struct RpcRequest {
+    pub method: String,
+    // Массив или объект. None, если параметры не переданы
+    pub params: Option<Value>,
+    // None - уведомление, ответ на него не отправляется
+    pub id: Option<RpcId>,
+}
+
+impl RpcRequest {
+    /*
+        Проверяет структуру запроса: jsonrpc = "2.0", method - строка, params - массив или объект,
+        id - число, строка или null. При ошибке возвращает id для ответа (null, если id не удалось прочитать)
+        и ошибку Invalid Request.
+    */
+    pub fn parse(value: Value) -> Result<RpcRequest, (RpcId, RpcError)> {
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => return Err((RpcId::Null, RpcError::invalid_request("request must be an object"))),
+        };
+        let id = match object.remove("id") {
+            None => None,
+            Some(Value::Null) => Some(RpcId::Null),
+            Some(Value::Number(number)) => Some(RpcId::Number(number)),
+            Some(Value::String(string)) => Some(RpcId::String(string)),
+            Some(_) => return Err((RpcId::Null, RpcError::invalid_request("id must be a number, a string or null"))),
+        };
+        let response_id = id.clone().unwrap_or(RpcId::Null);
+        if object.get("jsonrpc").and_then(Value::as_str) != Some(JSONRPC_VERSION) {
+            return Err((response_id, RpcError::invalid_request("jsonrpc must be \"2.0\"")));
+        }
+        let method = match object.remove("method") {
+            Some(Value::String(method)) => method,
+            _ => return Err((response_id, RpcError::invalid_request("method must be a string"))),
+        };
+        let params = match object.remove("params") {
+            None | Some(Value::Null) => None,
+            Some(params @ Value::Array(_)) | Some(params @ Value::Object(_)) => Some(params),
+            Some(_) => return Err((response_id, RpcError::invalid_request("params must be an array or an object"))),
+        };
+        Ok(RpcRequest { method, params, id })
+    }
+
+    // Разбирает параметры метода в типизированную структуру
+    pub fn params<T: DeserializeOwned>(&self) -> Result<T, RpcError> {
+        let params = self.params.clone().unwrap_or_else(|| Value::Array(Vec::new()));
+        serde_json::from_value(params).map_err(RpcError::invalid_params)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcResponse {
+    pub jsonrpc: &'static str,
+    // null, если id запроса не удалось прочитать
+    pub id: RpcId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn new(id: RpcId, result: Result<Value, RpcError>) -> RpcResponse {
+        match result {
+            Ok(result) => RpcResponse { jsonrpc: JSONRPC_VERSION, id, result: Some(result), error: None },
+            Err(error) => RpcResponse { jsonrpc: JSONRPC_VERSION, id, result: None, error: Some(error) },
+        }
+    }
+}
+
+// Параметры sendTransaction: [addr, to, amount, timestamp, fee, nonce, signature]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SendTransactionParams {
+    // Отправитель: открытый ключ ed25519 в base64
+    pub addr: String,
+    pub to: String,
+    // Десятичная строка или целое число
+    #[serde(with = "crate::amount")]
+    pub amount: u128,
+    pub timestamp: u128,
+    pub fee: u64,
+    pub nonce: u64,
+    // Подпись отправителя в base64
+    pub signature: String,
+}
+
+// Параметры getBlock: [index]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetBlockParams {
+    pub index: u64,
+}
+
+// Параметры getTransactionProof: [index, hash]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetTransactionProofParams {
+    pub index: u64,
+    pub hash: String,
+}
+
+// Параметры getBlockByHash и getTransactionByHash: [hash]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HashParams {
+    pub hash: String,
+}
+
+// Параметры getBalance и getNonce: [address]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddressParams {
+    pub address: String,
+}
+
+/*
+    Параметры getBlockRange: [from, to, limit]. Границы включительно.
+    limit ограничивает размер страницы (не больше MAX_BLOCK_RANGE), следующая страница запрашивается с from = next из ответа.
+*/
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetBlockRangeParams {
+    pub from: u64,
+    pub to: u64,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+/*
+    Параметры getPendingTransactions: [sender, recipient, offset, limit]. Все параметры необязательны.
+    sender и recipient фильтруют транзакции по отправителю и получателю,
+    offset и limit (не больше MAX_PENDING_PAGE) выбирают страницу из отфильтрованных транзакций.
+*/
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetPendingTransactionsParams {
+    #[serde(default)]
+    pub sender: Option<String>,
+    #[serde(default)]
+    pub recipient: Option<String>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+// Параметры subscribe: [kind, address]. address нужен только для addressTransactions
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscribeParams {
+    pub kind: String,
+    #[serde(default)]
+    pub address: Option<String>,
+}
+
+// Параметры unsubscribe: [subscription]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnsubscribeParams {
+    pub subscription: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let error = |value: Value| RpcRequest::parse(value).unwrap_err();
+
+        assert_eq!(error(json!([1, 2])).1.code, INVALID_REQUEST);
+        assert_eq!(error(json!({"jsonrpc": "2.0", "method": "getBlock", "id": [1]})).0, RpcId::Null);
+        let (id, e) = error(json!({"jsonrpc": "1.0", "method": "getBlock", "id": 7}));
+        assert_eq!((id, e.code), (RpcId::Number(7.into()), INVALID_REQUEST));
+        assert_eq!(error(json!({"jsonrpc": "2.0", "method": 5, "id": 7})).1.code, INVALID_REQUEST);
+        assert_eq!(error(json!({"jsonrpc": "2.0", "method": "getBlock", "params": 5, "id": 7})).1.code, INVALID_REQUEST);
+    }
+
+    #[test]
+    fn ids_and_notifications() {
+        let request = |id: Value| RpcRequest::parse(json!({"jsonrpc": "2.0", "method": "getBlock", "id": id})).unwrap().id;
+        assert_eq!(request(json!("abc")), Some(RpcId::String(String::from("abc"))));
+        assert_eq!(request(json!(null)), Some(RpcId::Null));
+        assert_eq!(request(json!(-3)), Some(RpcId::Number((-3).into())));
+
+        let notification = RpcRequest::parse(json!({"jsonrpc": "2.0", "method": "getBlock"})).unwrap();
+        assert_eq!(notification.id, None);
+        let response = RpcResponse::new(RpcId::String(String::from("a")), Ok(json!(1)));
+        assert_eq!(serde_json::to_value(response).unwrap(), json!({"jsonrpc": "2.0", "id": "a", "result": 1}));
+    }
+
+    #[test]
+    fn params_accept_positional_and_named_forms() {
+        let positional = RpcRequest::parse(json!({"jsonrpc": "2.0", "method": "getTransactionProof", "params": [3, "abc"], "id": 1})).unwrap();
+        let params: GetTransactionProofParams = positional.params().unwrap();
+        assert_eq!((params.index, params.hash.as_str()), (3, "abc"));
+
+        let named = RpcRequest::parse(json!({"jsonrpc": "2.0", "method": "getBlock", "params": {"index": 2}, "id": 1})).unwrap();
+        assert_eq!(named.params::<GetBlockParams>().unwrap().index, 2);
+
+        let wrong = RpcRequest::parse(json!({"jsonrpc": "2.0", "method": "getBlock", "params": ["two"], "id": 1})).unwrap();
+        assert_eq!(wrong.params::<GetBlockParams>().unwrap_err().code, INVALID_PARAMS);
+        let missing = RpcRequest::parse(json!({"jsonrpc": "2.0", "method": "getBlock", "id": 1})).unwrap();
+        assert_eq!(missing.params::<GetBlockParams>().unwrap_err().code, INVALID_PARAMS);
+
+        let range = RpcRequest::parse(json!({"jsonrpc": "2.0", "method": "getBlockRange", "params": [1, 5], "id": 1})).unwrap();
+        let params: GetBlockRangeParams = range.params().unwrap();
+        assert_eq!((params.from, params.to, params.limit), (1, 5, None));
+
+        let pending = RpcRequest::parse(json!({"jsonrpc": "2.0", "method": "getPendingTransactions", "id": 1})).unwrap();
+        let params: GetPendingTransactionsParams = pending.params().unwrap();
+        assert_eq!((params.sender, params.offset, params.limit), (None, 0, None));
+    }
+
+    // Случайные байты и искаженные запросы должны давать ошибку JSON-RPC, а не панику
+    #[test]
+    fn random_input_never_panics() {
+        use rand::{Rng, SeedableRng};
+        use rand::rngs::StdRng;
+
+        let mut rng = StdRng::seed_from_u64(25);
+        let valid = br#"[{"jsonrpc":"2.0","method":"sendTransaction","params":["a","b",1,2,3,4,"c"],"id":"x"}]"#;
+        for round in 0..5000 {
+            let bytes: Vec<u8> = if round % 2 == 0 {
+                let len = rng.gen_range(0..128);
+                (0..len).map(|_| rng.gen()).collect()
+            } else {
+                let mut bytes = valid.to_vec();
+                let position = rng.gen_range(0..bytes.len());
+                bytes[position] = rng.gen();
+                bytes.truncate(rng.gen_range(1..=bytes.len()));
+                bytes
+            };
+            let value = match parse_json(&bytes) {
+                Ok(value) => value,
+                Err(e) => {
+                    assert_eq!(e.code, PARSE_ERROR);
+                    continue;
+                }
+            };
+            let requests = match value {
+                Value::Array(batch) => batch,
+                value => vec![value],
+            };
+            for request in requests.into_iter().filter_map(|value| RpcRequest::parse(value).ok()) {
+                if let Ok(params) = request.params::<SendTransactionParams>() {
+                    let _ = crate::keys::decode_public_key(&params.addr);
+                    let _ = crate::keys::decode_signature(&params.signature);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_keys_are_invalid_params() {
+        assert_eq!(RpcError::from(crate::keys::decode_public_key("not base64!").unwrap_err()).code, INVALID_PARAMS);
+        assert_eq!(RpcError::from(crate::keys::decode_public_key("AAAA").unwrap_err()).code, INVALID_PARAMS);
+        assert_eq!(RpcError::from(crate::keys::decode_signature("").unwrap_err()).code, INVALID_PARAMS);
+        assert_eq!(parse_json(&[0xff, 0xfe]).unwrap_err().code, PARSE_ERROR);
+    }
+
+    #[test]
+    fn application_errors_have_distinct_codes() {
+        let funds: RpcError = MempoolError::State(StateError::InsufficientFunds { address: String::new(), balance: 0, required: 1 }).into();
+        assert_eq!(funds.code, INSUFFICIENT_FUNDS);
+        assert_eq!(RpcError::from(MempoolError::Duplicate).code, DUPLICATE_TRANSACTION);
+        assert_eq!(RpcError::from(MempoolError::NonceInUse).code, BAD_NONCE);
+    }
+}
@@ -2,279 +2,380 @@
 // 03.08.2024 OXI Ecosystem  All Right Reserved
 
 use actix_web::{web, App, HttpServer, Responder, HttpResponse};
-use serde::{Deserialize, Serialize};
 use serde_json::json;
 use actix_web::web::Data;
-// use crossbeam::channel::Sender;
 
 use crate::transaction::Transaction;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use serde_json::{Value, to_value};
-use log::info;
-use std::collections::BinaryHeap;
-use std::collections::HashSet;
+use log::{error, info};
 use tcp_module::message::Message;
 use tcp_module::message::MessageType;
-use ed25519_dalek::{PublicKey, Signature, Verifier};
-use base64::{encode, decode};
+use ed25519_dalek::Verifier;
+use crate::keys;
 use crate::transaction::Mempool;
-use tokio::sync::mpsc::{Receiver, Sender};
-use crate::block::Block;
-
-#[derive(Deserialize)]
-struct RpcRequest {
-    jsonrpc: String,
-    method: String,
-    params: Option<Vec<Value>>,
-    id: u64,
-}
-
-#[derive(Serialize)]
-struct RpcResponse {
-    jsonrpc: String,
-    id: Option<u64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    result: Option<serde_json::Value>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<RpcError>,
-}
-
-#[derive(Serialize)]
-struct RpcError {
-    code: i32,
-    message: String,
+use crate::middleware::ContentTypeJson;
+use crate::events::EventBus;
+use crate::subscriptions;
+use crate::storage::SharedBlockStore;
+use crate::state::WorldState;
+use crate::merkle;
+use crate::rpc::{
+    self, AddressParams, GetBlockParams, GetBlockRangeParams, GetPendingTransactionsParams, GetTransactionProofParams,
+    HashParams, RpcError, RpcId, RpcRequest, RpcResponse, SendTransactionParams,
+};
+use futures::future::join_all;
+use tokio::sync::mpsc::Sender;
+use tcp_module::reputation::Reputation;
+
+// Результат метода: значение для поля result или ошибка для поля error
+type RpcResult = Result<Value, RpcError>;
+
+// Настройки RPC сервера
+#[derive(Debug, Clone, Copy)]
+pub struct RpcSettings {
+    pub listen: SocketAddr,
+    // Максимальное количество запросов в одном пакете
+    pub max_batch_size: usize,
 }
 
 pub struct RPCServer {
     mempool: Arc<Mutex<Mempool>>,
     send_to_nodes_link: Sender<Message>,
-    chain: Arc<Mutex<Vec<Block>>>
+    store: SharedBlockStore,
+    state: Arc<Mutex<WorldState>>,
+    // Счета и блокировки узлов сети
+    reputation: Reputation,
 }
 
 impl RPCServer {
-    pub fn new(mempool: Arc<Mutex<Mempool>>, send_to_nodes_link: Sender<Message>, chain: Arc<Mutex<Vec<Block>>>) -> RPCServer {
+    pub fn new(mempool: Arc<Mutex<Mempool>>, send_to_nodes_link: Sender<Message>, store: SharedBlockStore, state: Arc<Mutex<WorldState>>, reputation: Reputation) -> RPCServer {
         RPCServer {
             mempool,
             send_to_nodes_link,
-            chain
+            store,
+            state,
+            reputation,
         }
     }
 
-    async fn add_transaction(&self, params: Option<Vec<Value>>) -> RpcResponse {
-        // Формируем params для удобной работы с параметрами
-        let array: Vec<Value> = match params {
-            Some(arr) => arr,
-            None => {
-                return RpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: None,
-                    result: Some(json!("Params required")),
-                    error: None,
-                }
-            }
+    /*
+        Обрабатывает один запрос из тела запроса или из пакета.
+        Для уведомления возвращает None: метод выполняется, но ответ не отправляется.
+    */
+    async fn handle(&self, value: Value) -> Option<RpcResponse> {
+        let request = match RpcRequest::parse(value) {
+            Ok(request) => request,
+            Err((id, error)) => return Some(RpcResponse::new(id, Err(error))),
         };
+        let result = self.dispatch(&request).await;
+        request.id.map(|id| RpcResponse::new(id, result))
+    }
 
-        let public_key_bytes = base64::decode(&array.get(0).and_then(Value::as_str).unwrap_or_default().to_string()).expect("Invalid public key encoding");
-        let signature_bytes = base64::decode(&array.get(5).and_then(Value::as_str).unwrap_or_default().to_string()).expect("Invalid signature encoding");
-        
-        let addr = array.get(0).and_then(Value::as_str).unwrap_or_default().to_string();
-        let to = array.get(1).and_then(Value::as_str).unwrap_or_default().to_string();
-        let amount = array.get(2).and_then(Value::as_u64).unwrap_or_default() as u128;
-        let timestamp = array.get(3).and_then(Value::as_u64).unwrap_or_default() as u128;
-        let fee = array.get(4).and_then(Value::as_u64).unwrap_or_default() as u64;
-
-        let message_from = format!("{}{}{}{}{}", addr, to, amount, timestamp, fee);
-        let message_bytes = message_from.as_bytes();
-
-        let public_key = PublicKey::from_bytes(&public_key_bytes).expect("Invalid public key");
-        let signature = Signature::from_bytes(&signature_bytes).expect("Invalid signature");
-
-        let is = public_key.verify(message_bytes, &signature).is_ok();
-
-        if is {
-            let transaction = Transaction::new(addr, to, amount, fee, timestamp);
-
-            let mut mempool_lock = self.mempool.lock().await;
-
-            match mempool_lock.add_transaction(transaction.clone()) {
-                true => {
-                    let message_to_nodes = Message::new(MessageType::Transaction, to_value(&transaction).unwrap());
-                    self.send_to_nodes_link.send(message_to_nodes).await;
-                    
-                    return RpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id: None,
-                        result: Some(json!("Transaction added")),
-                        error: None,
-                    }
-                }
-                false => {
-                    return RpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id: None,
-                        result: Some(json!("Транзакция уже добавлена.")),
-                        error: None,
-                    }
-                }
-            }
-        } else {
-            return RpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id: None,
-                result: Some(json!("Signature failed.")),
-                error: None,
-            }
+    // Вызывает метод запроса
+    pub async fn dispatch(&self, request: &RpcRequest) -> RpcResult {
+        match request.method.as_str() {
+            // Main methods
+            "sendTransaction" => self.add_transaction(request.params()?).await,
+            "getBlock" => self.get_block(request.params()?).await,
+            "getBlockByHash" => self.get_block_by_hash(request.params()?).await,
+            "getBlockNumber" => self.get_block_number().await,
+            "getLatestBlock" => self.get_latest_block().await,
+            "getBlockRange" => self.get_block_range(request.params()?).await,
+            "getTransactionByHash" => self.get_transaction_by_hash(request.params()?).await,
+            "getBalance" => self.get_balance(request.params()?).await,
+            "getNonce" => self.get_nonce(request.params()?).await,
+            "getTransactionProof" => self.get_transaction_proof(request.params()?).await,
+            "getBannedPeers" => self.get_banned_peers().await,
+            "getPeerScores" => self.get_peer_scores().await,
+            "getPendingTransactions" => self.get_pending_transactions(request.params()?).await,
+            "getMempoolStats" => self.get_mempool_stats().await,
+            "getTransactionStatus" => self.get_transaction_status(request.params()?).await,
+            method => Err(RpcError::method_not_found(method)),
         }
     }
 
-    async fn get_block(&self, params: Option<Vec<Value>>) -> RpcResponse {
-        let array: Vec<Value> = match params {
-            Some(arr) => arr,
-            None => {
-                return RpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: None,
-                    result: Some(json!("Params required")),
-                    error: None,
-                }
+    async fn add_transaction(&self, params: SendTransactionParams) -> RpcResult {
+        let public_key = keys::decode_public_key(&params.addr)?;
+        let signature = keys::decode_signature(&params.signature)?;
+
+        let message_bytes = Transaction::signing_bytes(&params.addr, &params.to, params.amount, params.timestamp, params.fee, params.nonce);
+        if public_key.verify(&message_bytes, &signature).is_err() {
+            return Err(RpcError::new(rpc::INVALID_SIGNATURE, "Signature failed."));
+        }
+
+        let transaction = Transaction::new(params.addr, params.to, params.amount, params.fee, params.timestamp, params.nonce)
+            .with_signature(params.signature);
+
+        let state = self.state.lock().await;
+        let mut mempool_lock = self.mempool.lock().await;
+        mempool_lock.add_transaction(transaction.clone(), &state)?;
+
+        let data = to_value(&transaction).map_err(RpcError::internal)?;
+        if let Err(e) = self.send_to_nodes_link.send(Message::new(MessageType::Transaction, data)).await {
+            error!("Failed to send transaction to nodes: {}", e);
+        }
+        Ok(json!("Transaction added"))
+    }
+
+    async fn get_block(&self, params: GetBlockParams) -> RpcResult {
+        match self.store.lock().await.get_by_height(params.index) {
+            Ok(Some(block)) => to_value(block).map_err(RpcError::internal),
+            Ok(None) => Err(RpcError::new(rpc::UNKNOWN_BLOCK, format!("Block {} not found", params.index))),
+            Err(e) => Err(RpcError::internal(e)),
+        }
+    }
+
+    async fn get_block_by_hash(&self, params: HashParams) -> RpcResult {
+        match self.store.lock().await.get_by_hash(&params.hash) {
+            Ok(Some(block)) => to_value(block).map_err(RpcError::internal),
+            Ok(None) => Err(RpcError::new(rpc::UNKNOWN_BLOCK, format!("Block {} not found", params.hash))),
+            Err(e) => Err(RpcError::internal(e)),
+        }
+    }
+
+    // Высота последнего блока основной цепочки
+    async fn get_block_number(&self) -> RpcResult {
+        match self.store.lock().await.height() {
+            Some(height) => Ok(json!(height)),
+            None => Err(RpcError::new(rpc::UNKNOWN_BLOCK, "Chain is empty")),
+        }
+    }
+
+    async fn get_latest_block(&self) -> RpcResult {
+        let store = self.store.lock().await;
+        let height = store.height().ok_or_else(|| RpcError::new(rpc::UNKNOWN_BLOCK, "Chain is empty"))?;
+        match store.get_by_height(height) {
+            Ok(Some(block)) => to_value(block).map_err(RpcError::internal),
+            Ok(None) => Err(RpcError::new(rpc::UNKNOWN_BLOCK, format!("Block {} not found", height))),
+            Err(e) => Err(RpcError::internal(e)),
+        }
+    }
+
+    /*
+        Блоки с from по to включительно, не больше limit (MAX_BLOCK_RANGE) за запрос.
+        to ограничивается высотой цепочки. next - высота, с которой запрашивать следующую страницу, null - блоков больше нет.
+    */
+    async fn get_block_range(&self, params: GetBlockRangeParams) -> RpcResult {
+        if params.from > params.to {
+            return Err(RpcError::invalid_params("from must not be greater than to"));
+        }
+        let limit = params.limit.unwrap_or(rpc::MAX_BLOCK_RANGE);
+        if limit == 0 || limit > rpc::MAX_BLOCK_RANGE {
+            return Err(RpcError::invalid_params(format!("limit must be between 1 and {}", rpc::MAX_BLOCK_RANGE)));
+        }
+
+        let store = self.store.lock().await;
+        let height = store.height().ok_or_else(|| RpcError::new(rpc::UNKNOWN_BLOCK, "Chain is empty"))?;
+        if params.from > height {
+            return Err(RpcError::new(rpc::UNKNOWN_BLOCK, format!("Block {} not found", params.from)));
+        }
+        let to = params.to.min(height);
+        let last = to.min(params.from + limit - 1);
+
+        let mut blocks = Vec::new();
+        for index in params.from..=last {
+            match store.get_by_height(index) {
+                Ok(Some(block)) => blocks.push(block),
+                Ok(None) => return Err(RpcError::new(rpc::UNKNOWN_BLOCK, format!("Block {} not found", index))),
+                Err(e) => return Err(RpcError::internal(e)),
             }
+        }
+        let next = if last < to { Some(last + 1) } else { None };
+        Ok(json!({ "blocks": to_value(blocks).map_err(RpcError::internal)?, "next": next }))
+    }
+
+    // Транзакция основной цепочки с высотой и хешем блока и номером в блоке
+    async fn get_transaction_by_hash(&self, params: HashParams) -> RpcResult {
+        let store = self.store.lock().await;
+        let not_found = || RpcError::new(rpc::UNKNOWN_TRANSACTION, format!("Transaction {} not found", params.hash));
+        let location = store.transaction_location(&params.hash).ok_or_else(not_found)?;
+        let block = match store.get_by_height(location.height) {
+            Ok(Some(block)) => block,
+            Ok(None) => return Err(not_found()),
+            Err(e) => return Err(RpcError::internal(e)),
         };
+        let transaction = block.transactions.get(location.position).ok_or_else(not_found)?;
+        Ok(json!({
+            "transaction": to_value(transaction).map_err(RpcError::internal)?,
+            "block_height": location.height,
+            "block_hash": block.hash,
+            "position": location.position,
+        }))
+    }
 
-        let index_block = array.get(0).and_then(Value::as_u64).unwrap_or_default() as usize;
+    // Баланс аккаунта по состоянию основной цепочки. Десятичная строка: u128 не помещается в число JSON
+    async fn get_balance(&self, params: AddressParams) -> RpcResult {
+        Ok(Value::String(self.state.lock().await.balance(&params.address).to_string()))
+    }
 
-        let chain = self.chain.lock().await;
+    // Nonce, который должна иметь следующая транзакция аккаунта
+    async fn get_nonce(&self, params: AddressParams) -> RpcResult {
+        Ok(json!(self.state.lock().await.nonce(&params.address)))
+    }
 
-        if let Some(value) = chain.get(index_block) {
-            return RpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id: None,
-                result: Some(json!(value)),
-                error: None,
-            };
-        } else {
-            return RpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id: None,
-                result: None,
-                error: Some(RpcError { code: 0, message: "Индекс не найден".to_string() }),
+    // Доказательство включения транзакции в блок. Параметры: [номер блока, хеш транзакции]
+    async fn get_transaction_proof(&self, params: GetTransactionProofParams) -> RpcResult {
+        let block = match self.store.lock().await.get_by_height(params.index) {
+            Ok(Some(block)) => block,
+            Ok(None) => return Err(RpcError::new(rpc::UNKNOWN_BLOCK, format!("Block {} not found", params.index))),
+            Err(e) => return Err(RpcError::internal(e)),
+        };
+
+        match merkle::build_proof(&block.transaction_hashes(), &params.hash) {
+            // Блок из хранилища мог не совпасть с корнем своего заголовка: такое доказательство клиенту не отдается
+            Some(proof) if !merkle::verify_proof(&params.hash, &proof, &block.header.transactions_root) => {
+                Err(RpcError::internal(format!("proof for {} does not match block {}", params.hash, block.hash)))
             }
+            Some(proof) => Ok(json!({
+                "block_hash": block.hash,
+                "header": to_value(&block.header).map_err(RpcError::internal)?,
+                "proof": proof,
+            })),
+            None => Err(RpcError::new(rpc::UNKNOWN_TRANSACTION, "Transaction not found in block")),
         }
     }
 
-    async fn say_hello_handler(&self, _params: Option<Vec<Value>>, mempool: Arc<Mutex<BinaryHeap<Transaction>>>) -> RpcResponse {
-        let guard = mempool.lock().await;
-        RpcResponse {
-            jsonrpc: "2.0".to_string(),
-            id: None,
-            result: Some(json!(guard.iter().cloned().collect::<Vec<Transaction>>())),
-            error: None,
+    // Действующие блокировки узлов: IP или ключ узла, время окончания (None - постоянная), причина
+    async fn get_banned_peers(&self) -> RpcResult {
+        Ok(json!(self.reputation.bans().await))
+    }
+
+    // Счета подключенных узлов
+    async fn get_peer_scores(&self) -> RpcResult {
+        Ok(json!(self.reputation.peer_scores().await))
+    }
+
+    /*
+        Транзакции мемпула в порядке включения в блок с фильтрами по отправителю и получателю.
+        total - количество транзакций после фильтрации, next - offset следующей страницы или null.
+    */
+    async fn get_pending_transactions(&self, params: GetPendingTransactionsParams) -> RpcResult {
+        let limit = params.limit.unwrap_or(rpc::MAX_PENDING_PAGE);
+        if limit == 0 || limit > rpc::MAX_PENDING_PAGE {
+            return Err(RpcError::invalid_params(format!("limit must be between 1 and {}", rpc::MAX_PENDING_PAGE)));
         }
+
+        let transactions: Vec<Transaction> = self
+            .mempool
+            .lock()
+            .await
+            .get_all_transactions()
+            .into_iter()
+            .filter(|tx| params.sender.as_ref().is_none_or(|sender| &tx.addr == sender))
+            .filter(|tx| params.recipient.as_ref().is_none_or(|recipient| &tx.to == recipient))
+            .collect();
+        let total = transactions.len();
+        let page: Vec<Transaction> = transactions.into_iter().skip(params.offset).take(limit).collect();
+        let end = params.offset.saturating_add(page.len());
+        let next = if end < total { Some(end) } else { None };
+        Ok(json!({ "transactions": to_value(page).map_err(RpcError::internal)?, "total": total, "next": next }))
     }
 
-    async fn some_other_handler(&self, _params: Option<Vec<Value>>) -> RpcResponse {
-        RpcResponse {
-            jsonrpc: "2.0".to_string(),
-            id: None,
-            result: Some(json!("This is another method!")),
-            error: None,
+    async fn get_mempool_stats(&self) -> RpcResult {
+        Ok(json!(self.mempool.lock().await.stats()))
+    }
+
+    /*
+        Состояние транзакции: included - в блоке основной цепочки, pending - в мемпуле,
+        dropped - выброшена из мемпула без включения в блок (с причиной), unknown - узлу неизвестна.
+    */
+    async fn get_transaction_status(&self, params: HashParams) -> RpcResult {
+        let store = self.store.lock().await;
+        if let Some(location) = store.transaction_location(&params.hash) {
+            let block_hash = match store.get_by_height(location.height) {
+                Ok(block) => block.map(|block| block.hash),
+                Err(e) => return Err(RpcError::internal(e)),
+            };
+            return Ok(json!({
+                "status": "included",
+                "block_height": location.height,
+                "block_hash": block_hash,
+                "position": location.position,
+            }));
+        }
+
+        let mempool = self.mempool.lock().await;
+        if mempool.contains(&params.hash) {
+            return Ok(json!({ "status": "pending" }));
+        }
+        match mempool.dropped_reason(&params.hash) {
+            Some(reason) => Ok(json!({ "status": "dropped", "reason": reason })),
+            None => Ok(json!({ "status": "unknown" })),
         }
     }
 }
 
-async fn rpc_handler(req_body: String, server: Data<Arc<Mutex<RPCServer>>>) -> impl Responder {
-    let request: RpcRequest = match serde_json::from_str(&req_body) {
-        Ok(request) => request,
-        Err(e) => {
-            let response = RpcResponse {
-                jsonrpc: "2.0".to_string(),
-                id: None,
-                result: None,
-                error: Some(RpcError {
-                    code: 0,
-                    message: e.to_string(),
-                }),
+/*
+    Принимает один запрос или пакет запросов (массив).
+    Запросы пакета выполняются одновременно, ответы возвращаются в порядке запросов без ответов на уведомления.
+    Если отвечать не на что (только уведомления), возвращается пустой ответ 204.
+*/
+async fn rpc_handler(req_body: web::Bytes, server: Data<RPCServer>, settings: Data<RpcSettings>) -> impl Responder {
+    let value = match rpc::parse_json(&req_body) {
+        Ok(value) => value,
+        Err(e) => return HttpResponse::BadRequest().json(RpcResponse::new(RpcId::Null, Err(e))),
+    };
+
+    let batch = match value {
+        Value::Array(batch) => batch,
+        value => {
+            return match server.handle(value).await {
+                Some(response) if response.error.as_ref().map(|e| e.code == rpc::INVALID_REQUEST).unwrap_or(false) => {
+                    HttpResponse::BadRequest().json(response)
+                }
+                Some(response) => HttpResponse::Ok().json(response),
+                None => HttpResponse::NoContent().finish(),
             };
-            return HttpResponse::BadRequest().json(response);
         }
     };
 
-    let server = server.lock().await;
-
-    let mut response = match request.method.as_str() {
-        // Main methods
-        "sendTransaction" => server.add_transaction(request.params).await,
-        "getBlock" => server.get_block(request.params).await,
-        // Test methods
-        // "say_hello" => server.say_hello_handler(request.params, mempool_arc).await,
-        // "some_other_method" => server.some_other_handler(request.params).await,
-        _ => RpcResponse {
-            jsonrpc: "2.0".to_string(),
-            id: None,
-            result: None,
-            error: Some(RpcError {
-                code: 0,
-                message: "Method not found".to_string(),
-            }),
-        }, 
-    };
-    response.id = Some(request.id);
-    HttpResponse::Ok().json(response)
+    if batch.is_empty() {
+        return HttpResponse::BadRequest().json(RpcResponse::new(RpcId::Null, Err(RpcError::invalid_request("empty batch"))));
+    }
+    if batch.len() > settings.max_batch_size {
+        let error = RpcError::invalid_request(format!("batch of {} requests exceeds the limit of {}", batch.len(), settings.max_batch_size));
+        return HttpResponse::BadRequest().json(RpcResponse::new(RpcId::Null, Err(error)));
+    }
+
+    let responses: Vec<RpcResponse> = join_all(batch.into_iter().map(|value| server.handle(value)))
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+    if responses.is_empty() {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::Ok().json(responses)
+    }
 }
 
 
-pub async fn start_rpc_server(mempool: Arc<Mutex<Mempool>>, send_to_nodes_link: Sender<Message>, chain: Arc<Mutex<Vec<Block>>>)-> std::io::Result<()> {
-    info!("RPC Server Starting on 8080");
+pub async fn start_rpc_server(settings: RpcSettings, server: RPCServer, events: EventBus)-> std::io::Result<()> {
+    info!("RPC Server Starting on {}", settings.listen);
 
-    let server = Arc::new(Mutex::new(RPCServer::new(mempool, send_to_nodes_link, chain)));
+    // Сервер не блокируется целиком: методы берут только нужные им блокировки, и запросы выполняются одновременно
+    let server = Data::new(server);
+    let settings_data = Data::new(settings);
+    let events = Data::new(events);
 
     HttpServer::new(move || {
         App::new()
-            .app_data(Data::new(Arc::clone(&server)))
+            .wrap(ContentTypeJson)
+            .app_data(server.clone())
+            .app_data(settings_data.clone())
+            .app_data(events.clone())
             .route("/rpc", web::post().to(rpc_handler))
+            .route("/ws", web::get().to(subscriptions::ws_handler))
     })
-    .bind("0.0.0.0:8080")?
+    .bind(settings.listen)?
     .run()
     .await
 }
 
 
-
-    // fn build_responce(result: Option<serde_json::Value>, error: Option<RpcError>) 
-
-    // async fn mine_transactions() -> RpcResponse {
-    //     // let validator_address = {
-    //     //     let pos = pos.lock().unwrap();
-    //     //     pos.select_validator().map(|v| v.address.clone())
-    //     // };
-
-    //     let message;
-
-    //     if let Some(validator_address) = validator_address {
-    //         // let mut blockchain = blockchain.lock().unwrap();
-    //         // blockchain.mine_pending_transactions(validator_address);
-    //         message = "Block mined";
-    //     } else {
-    //         message = "No validators available";
-    //     }
-
-    //     RpcResponse {
-    //         jsonrpc: "2.0".to_string(),
-    //         id: None,
-    //         result: Some(message.into()),
-    //         error: None,
-    //     }
-    // }
-
-    // async fn get_blockchain() -> RpcResponse {
-    //     RpcResponse {
-    //         jsonrpc: "2.0".to_string(),
-    //         id: None,
-    //         result: Some(json!(blockchain_copy)),
-    //         error: None,
-    //     }
-    // }
-
     // 03.08.2024 OXI Ecosystem  All Right Reserved
 
 /*
@@ -283,6 +384,7 @@ pub async fn start_rpc_server(mempool: Arc<Mutex<Mempool>>, send_to_nodes_link:
     2. Проверяет валидность запроса
     3. Проверяет подпись, данные, структуру запроса 
     4. Отправляет пользователю ответ о состоянии его запроса 
+    5. Отправляет уведомления по подпискам WebSocket (модуль subscriptions)
 
     Может читать данные из блокчейна и отправлять транзакции в очередь мемпула.
 */
\ No newline at end of file
@@ -0,0 +1,313 @@
+/*
+    Состояние мира: балансы всех аккаунтов.
+    Обновляется при создании каждого блока и восстанавливается из цепочки при запуске узла.
+    Используется RPC сервером и мемпулом, чтобы отклонять транзакции, которые отправитель не может оплатить.
+*/
+use std::collections::HashMap;
+use std::fmt;
+use serde::Serialize;
+use crate::block::Block;
+use crate::transaction::Transaction;
+
+// Адрес, от имени которого создаются награды валидаторам и начальные балансы
+pub const NETWORK_ADDRESS: &str = "network";
+// Награда валидатору за созданный блок
+pub const BLOCK_REWARD: u128 = 50;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Account {
+    #[serde(with = "crate::amount")]
+    pub balance: u128,
+    // Nonce, который должна иметь следующая транзакция аккаунта
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum StateError {
+    // У отправителя недостаточно средств для amount + fee
+    InsufficientFunds {
+        address: String,
+        #[serde(with = "crate::amount")]
+        balance: u128,
+        #[serde(with = "crate::amount")]
+        required: u128,
+    },
+    // Транзакция от имени сети в неположенном месте или с неверной наградой
+    InvalidReward(String),
+    // Транзакция с таким nonce уже была исполнена
+    NonceTooLow { address: String, expected: u64, got: u64 },
+    // Перед транзакцией должны быть исполнены транзакции с меньшими nonce
+    NonceTooHigh { address: String, expected: u64, got: u64 },
+    Overflow,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::InsufficientFunds { address, balance, required } => {
+                write!(f, "Insufficient funds: {} has {}, required {}", address, balance, required)
+            }
+            StateError::InvalidReward(reason) => write!(f, "Invalid reward transaction: {}", reason),
+            StateError::NonceTooLow { address, expected, got } => {
+                write!(f, "Nonce too low for {}: expected {}, got {}", address, expected, got)
+            }
+            StateError::NonceTooHigh { address, expected, got } => {
+                write!(f, "Nonce too high for {}: expected {}, got {}", address, expected, got)
+            }
+            StateError::Overflow => write!(f, "Balance overflow"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorldState {
+    accounts: HashMap<String, Account>,
+}
+
+impl WorldState {
+    pub fn new() -> WorldState {
+        WorldState::default()
+    }
+
+    // Восстанавливает состояние, последовательно применяя все блоки цепочки
+    pub fn from_blocks(blocks: &[Block]) -> Result<WorldState, StateError> {
+        let mut state = WorldState::new();
+        for block in blocks {
+            state.apply_block(block)?;
+        }
+        Ok(state)
+    }
+
+    pub fn balance(&self, address: &str) -> u128 {
+        self.accounts.get(address).map(|account| account.balance).unwrap_or(0)
+    }
+
+    pub fn nonce(&self, address: &str) -> u64 {
+        self.accounts.get(address).map(|account| account.nonce).unwrap_or(0)
+    }
+
+    // Сумма, которую отправитель тратит транзакцией
+    pub fn transaction_cost(tx: &Transaction) -> Result<u128, StateError> {
+        tx.amount.checked_add(tx.fee as u128).ok_or(StateError::Overflow)
+    }
+
+    /*
+        Проверяет, может ли отправитель оплатить транзакцию и не была ли она уже исполнена.
+        pending - сумма, уже зарезервированная отправителем в мемпуле.
+        Nonce больше ожидаемого допускается: такая транзакция ждет в мемпуле предыдущие.
+    */
+    pub fn check_transaction(&self, tx: &Transaction, pending: u128) -> Result<(), StateError> {
+        if tx.addr == NETWORK_ADDRESS {
+            return Err(StateError::InvalidReward("network transactions can not be submitted".to_string()));
+        }
+        let expected = self.nonce(&tx.addr);
+        if tx.nonce < expected {
+            return Err(StateError::NonceTooLow {
+                address: tx.addr.clone(),
+                expected,
+                got: tx.nonce,
+            });
+        }
+        let required = Self::transaction_cost(tx)?
+            .checked_add(pending)
+            .ok_or(StateError::Overflow)?;
+        let balance = self.balance(&tx.addr);
+        if balance < required {
+            return Err(StateError::InsufficientFunds {
+                address: tx.addr.clone(),
+                balance,
+                required,
+            });
+        }
+        Ok(())
+    }
+
+    /*
+        Применяет обычную транзакцию: списывает amount + fee у отправителя,
+        зачисляет amount получателю и fee валидатору. Nonce транзакции должен совпадать с nonce аккаунта.
+    */
+    pub fn apply_transaction(&mut self, tx: &Transaction, validator: &str) -> Result<(), StateError> {
+        self.check_transaction(tx, 0)?;
+        let expected = self.nonce(&tx.addr);
+        if tx.nonce != expected {
+            return Err(StateError::NonceTooHigh {
+                address: tx.addr.clone(),
+                expected,
+                got: tx.nonce,
+            });
+        }
+        let cost = Self::transaction_cost(tx)?;
+
+        let sender = self.accounts.entry(tx.addr.clone()).or_default();
+        sender.balance -= cost;
+        sender.nonce += 1;
+        self.credit(&tx.to, tx.amount)?;
+        self.credit(validator, tx.fee as u128)
+    }
+
+    /*
+        Применяет блок целиком. Состояние изменяется только если все транзакции блока корректны.
+        В генезис-блоке разрешены только транзакции от сети (начальные балансы).
+        В остальных блоках должна быть ровно одна транзакция от сети - награда валидатору.
+    */
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), StateError> {
+        let mut next = self.clone();
+
+        if block.header.index == 0 {
+            for tx in &block.transactions {
+                if tx.addr != NETWORK_ADDRESS {
+                    return Err(StateError::InvalidReward("genesis block may only contain network transactions".to_string()));
+                }
+                next.credit(&tx.to, tx.amount)?;
+            }
+            *self = next;
+            return Ok(());
+        }
+
+        let rewards: Vec<&Transaction> = block.transactions.iter().filter(|tx| tx.addr == NETWORK_ADDRESS).collect();
+        let reward = match rewards.as_slice() {
+            [reward] => *reward,
+            _ => return Err(StateError::InvalidReward(format!("expected one reward transaction, found {}", rewards.len()))),
+        };
+        if reward.amount != BLOCK_REWARD || reward.fee != 0 {
+            return Err(StateError::InvalidReward(format!("reward must be {} without fee", BLOCK_REWARD)));
+        }
+        if reward.to != block.header.validator {
+            return Err(StateError::InvalidReward("reward must be paid to the block validator".to_string()));
+        }
+
+        for tx in block.transactions.iter().filter(|tx| tx.addr != NETWORK_ADDRESS) {
+            next.apply_transaction(tx, &reward.to)?;
+        }
+        next.credit(&reward.to, reward.amount)?;
+
+        *self = next;
+        Ok(())
+    }
+
+    fn credit(&mut self, address: &str, amount: u128) -> Result<(), StateError> {
+        let account = self.accounts.entry(address.to_string()).or_default();
+        account.balance = account.balance.checked_add(amount).ok_or(StateError::Overflow)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::blockchain::Blockchain;
+
+    const VALIDATOR: &str = "validator";
+
+    fn transfer(from: &str, to: &str, amount: u128, nonce: u64) -> Transaction {
+        Transaction::new(from.to_string(), to.to_string(), amount, 1, 0, nonce)
+    }
+
+    fn reward(index: u64) -> Transaction {
+        Transaction::new(NETWORK_ADDRESS.to_string(), VALIDATOR.to_string(), BLOCK_REWARD, 0, 0, index)
+    }
+
+    fn block(parent: &Block, mut transactions: Vec<Transaction>) -> Block {
+        let index = parent.header.index + 1;
+        transactions.push(reward(index));
+        Block::new(index, parent.hash.clone(), transactions, VALIDATOR.to_string(), index)
+    }
+
+    fn genesis(balances: &[(&str, u128)]) -> Block {
+        let balances: BTreeMap<String, u128> = balances.iter().map(|(address, amount)| (address.to_string(), *amount)).collect();
+        Blockchain::create_genesis_block(&balances)
+    }
+
+    #[test]
+    fn overdraft_is_rejected() {
+        let genesis = genesis(&[("alice", 100)]);
+        let mut state = WorldState::from_blocks(std::slice::from_ref(&genesis)).unwrap();
+
+        // amount + fee превышает баланс
+        let overdraft = transfer("alice", "bob", 100, 0);
+        assert!(matches!(state.check_transaction(&overdraft, 0), Err(StateError::InsufficientFunds { balance: 100, required: 101, .. })));
+        assert!(state.check_transaction(&transfer("alice", "bob", 50, 0), 50).is_err());
+
+        let before = state.clone();
+        let result = state.apply_block(&block(&genesis, vec![transfer("alice", "bob", 60, 0), transfer("alice", "bob", 60, 1)]));
+        assert!(matches!(result, Err(StateError::InsufficientFunds { .. })));
+        assert_eq!(state, before);
+    }
+
+    #[test]
+    fn credit_overflow_is_rejected() {
+        let genesis = genesis(&[("alice", 100), ("bob", u128::MAX)]);
+        let mut state = WorldState::from_blocks(std::slice::from_ref(&genesis)).unwrap();
+
+        let before = state.clone();
+        assert_eq!(state.apply_block(&block(&genesis, vec![transfer("alice", "bob", 1, 0)])), Err(StateError::Overflow));
+        assert_eq!(state, before);
+
+        let huge = Transaction::new(String::from("alice"), String::from("bob"), u128::MAX, u64::MAX, 0, 0);
+        assert_eq!(WorldState::transaction_cost(&huge), Err(StateError::Overflow));
+    }
+
+    #[test]
+    fn rebuilt_state_matches_incremental_state() {
+        let genesis = genesis(&[("alice", 1000), ("bob", 10)]);
+        let first = block(&genesis, vec![transfer("alice", "bob", 100, 0), transfer("alice", "carol", 5, 1)]);
+        let second = block(&first, vec![transfer("bob", "carol", 50, 0)]);
+        let third = block(&second, Vec::new());
+        let blocks = vec![genesis, first, second, third];
+
+        let mut incremental = WorldState::new();
+        for block in &blocks {
+            incremental.apply_block(block).unwrap();
+        }
+        assert_eq!(WorldState::from_blocks(&blocks).unwrap(), incremental);
+        assert_eq!((incremental.balance("alice"), incremental.nonce("alice")), (893, 2));
+        assert_eq!((incremental.balance("carol"), incremental.balance(VALIDATOR)), (55, 3 * BLOCK_REWARD + 3));
+    }
+
+    #[test]
+    fn invalid_rewards_are_rejected() {
+        let genesis = genesis(&[]);
+        let state = WorldState::from_blocks(std::slice::from_ref(&genesis)).unwrap();
+        let rejects = |block: &Block| matches!(state.clone().apply_block(block), Err(StateError::InvalidReward(_)));
+
+        let mut duplicate = block(&genesis, Vec::new());
+        duplicate.transactions.push(reward(2));
+        assert!(rejects(&duplicate));
+
+        let mut missing = block(&genesis, Vec::new());
+        missing.transactions.clear();
+        assert!(rejects(&missing));
+
+        let mut wrong_validator = block(&genesis, Vec::new());
+        wrong_validator.transactions[0] = Transaction::new(NETWORK_ADDRESS.to_string(), String::from("mallory"), BLOCK_REWARD, 0, 0, 1);
+        assert!(rejects(&wrong_validator));
+
+        let mut wrong_amount = block(&genesis, Vec::new());
+        wrong_amount.transactions[0] = Transaction::new(NETWORK_ADDRESS.to_string(), VALIDATOR.to_string(), BLOCK_REWARD + 1, 0, 0, 1);
+        assert!(rejects(&wrong_amount));
+
+        let mut with_fee = block(&genesis, Vec::new());
+        with_fee.transactions[0] = Transaction::new(NETWORK_ADDRESS.to_string(), VALIDATOR.to_string(), BLOCK_REWARD, 1, 0, 1);
+        assert!(rejects(&with_fee));
+
+        assert!(state.check_transaction(&reward(1), 0).is_err());
+    }
+
+    #[test]
+    fn nonces_must_be_in_order_within_block() {
+        let genesis = genesis(&[("alice", 100)]);
+        let state = WorldState::from_blocks(std::slice::from_ref(&genesis)).unwrap();
+
+        let reversed = block(&genesis, vec![transfer("alice", "bob", 1, 1), transfer("alice", "bob", 1, 0)]);
+        assert!(matches!(state.clone().apply_block(&reversed), Err(StateError::NonceTooHigh { expected: 0, got: 1, .. })));
+
+        let replayed = block(&genesis, vec![transfer("alice", "bob", 1, 0), transfer("alice", "carol", 1, 0)]);
+        assert!(matches!(state.clone().apply_block(&replayed), Err(StateError::NonceTooLow { expected: 1, got: 0, .. })));
+
+        let mut next = state.clone();
+        next.apply_block(&block(&genesis, vec![transfer("alice", "bob", 1, 0), transfer("alice", "bob", 1, 1)])).unwrap();
+        assert_eq!(next.nonce("alice"), 2);
+        assert!(matches!(next.check_transaction(&transfer("alice", "bob", 1, 1), 0), Err(StateError::NonceTooLow { .. })));
+    }
+}
@@ -0,0 +1,539 @@
+/*
+    Хранилище блоков. Позволяет сохранять цепочку между перезапусками узла.
+
+    FileBlockStore записывает блоки в append-only сегменты (segment_000000.dat, segment_000001.dat, ...)
+    Формат одной записи: [длина данных: u32 LE][контрольная сумма: 4 байта sha256][блок в JSON]
+    При открытии все сегменты сканируются, строится индекс по высоте, по хешу и индекс транзакций основной цепочки.
+    Если последняя запись была записана не полностью (узел упал во время записи), она обрезается.
+
+    MemoryBlockStore хранит блоки только в памяти и используется, если хранение на диске не требуется.
+*/
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Digest};
+use tokio::sync::Mutex;
+use log::{error, info, warn};
+use crate::block::Block;
+
+// Максимальный размер одного сегмента, после которого создается новый
+const SEGMENT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+// Размер заголовка записи: длина + контрольная сумма
+const RECORD_HEADER_SIZE: usize = 8;
+
+// Тип хранилища блоков в настройках узла
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    // Блоки на диске (FileBlockStore)
+    #[default]
+    File,
+    // Блоки только в памяти (MemoryBlockStore)
+    Memory,
+}
+
+// Общее хранилище, доступное из разных потоков
+pub type SharedBlockStore = Arc<Mutex<Box<dyn BlockStore>>>;
+
+// Интерфейс хранилища блоков
+pub trait BlockStore: Send {
+    // Добавляет блок на высоту block.header.index. Блоки выше этой высоты исключаются из индекса.
+    fn append(&mut self, block: &Block) -> io::Result<()> {
+        self.append_branch(std::slice::from_ref(block))
+    }
+
+    /*
+        Добавляет блоки ветки подряд, начиная с высоты первого блока, и исключает из индекса блоки выше нее.
+        Ветка добавляется целиком или не добавляется вовсе: при ошибке основная цепочка хранилища не меняется.
+    */
+    fn append_branch(&mut self, blocks: &[Block]) -> io::Result<()>;
+
+    fn get_by_height(&self, height: u64) -> io::Result<Option<Block>>;
+
+    // Блок основной цепочки по хешу. Блоки, исключенные из цепочки при смене ветки, не возвращаются
+    fn get_by_hash(&self, hash: &str) -> io::Result<Option<Block>>;
+
+    // Положение транзакции в основной цепочке
+    fn transaction_location(&self, hash: &str) -> Option<TransactionLocation>;
+
+    // Высота последнего сохраненного блока. None, если хранилище пустое
+    fn height(&self) -> Option<u64>;
+
+    // Загружает все блоки основной цепочки по порядку
+    fn blocks(&self) -> io::Result<Vec<Block>> {
+        let mut blocks = Vec::new();
+        if let Some(height) = self.height() {
+            for index in 0..=height {
+                match self.get_by_height(index)? {
+                    Some(block) => blocks.push(block),
+                    None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("Block {} is missing", index))),
+                }
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+// Проверяет, что блоки ветки идут подряд и ветка начинается не выше следующей высоты хранилища
+fn check_branch(blocks: &[Block], stored: usize) -> io::Result<()> {
+    let first = match blocks.first() {
+        Some(block) => block.header.index,
+        None => return Ok(()),
+    };
+    if first as usize > stored {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Block {} does not follow stored height {}", first, stored),
+        ));
+    }
+    if let Some((_, block)) = blocks.iter().enumerate().find(|(i, block)| block.header.index != first + *i as u64) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Block {} breaks the branch starting at {}", block.header.index, first),
+        ));
+    }
+    Ok(())
+}
+
+// Положение транзакции в основной цепочке: высота блока и номер транзакции в блоке
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TransactionLocation {
+    pub height: u64,
+    pub position: usize,
+}
+
+/*
+    Индекс транзакций основной цепочки: хеш транзакции -> положение.
+    Для каждой высоты хранятся хеши ее транзакций, чтобы при смене ветки удалить из индекса транзакции исключенных блоков.
+*/
+#[derive(Default)]
+struct TransactionIndex {
+    locations: HashMap<String, TransactionLocation>,
+    heights: Vec<Vec<String>>,
+}
+
+impl TransactionIndex {
+    // Добавляет транзакции блока, предварительно удаляя транзакции блоков на его высоте и выше
+    fn index(&mut self, block: &Block) {
+        let height = block.header.index as usize;
+        for hashes in self.heights.drain(height.min(self.heights.len())..) {
+            for hash in hashes {
+                self.locations.remove(&hash);
+            }
+        }
+        for (position, tx) in block.transactions.iter().enumerate() {
+            self.locations.insert(tx.hash.clone(), TransactionLocation { height: block.header.index, position });
+        }
+        self.heights.push(block.transaction_hashes());
+    }
+
+    fn get(&self, hash: &str) -> Option<TransactionLocation> {
+        self.locations.get(hash).copied()
+    }
+}
+
+// Расположение записи в сегменте
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Location {
+    segment: u32,
+    offset: u64,
+    len: u32,
+}
+
+pub struct FileBlockStore {
+    dir: PathBuf,
+    // Индекс основной цепочки: высота -> запись
+    heights: Vec<Location>,
+    // Индекс всех записанных блоков: хеш -> запись
+    hashes: HashMap<String, Location>,
+    transactions: TransactionIndex,
+    // Текущий сегмент для записи
+    segment: u32,
+    writer: File,
+    segment_size: u64,
+}
+
+impl FileBlockStore {
+    // Открывает хранилище в каталоге dir, при необходимости создает его
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<FileBlockStore> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments: Vec<u32> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Self::parse_segment_name(&entry.file_name().to_string_lossy()))
+            .collect();
+        segments.sort_unstable();
+
+        let mut heights = Vec::new();
+        let mut hashes = HashMap::new();
+        let mut transactions = TransactionIndex::default();
+
+        for (position, &segment) in segments.iter().enumerate() {
+            let is_last = position + 1 == segments.len();
+            let path = Self::segment_path(&dir, segment);
+            let data = fs::read(&path)?;
+            let mut offset = 0usize;
+
+            while offset < data.len() {
+                match Self::decode_record(&data[offset..]) {
+                    Some((block, len)) => {
+                        let location = Location {
+                            segment,
+                            offset: offset as u64,
+                            len: len as u32,
+                        };
+                        Self::index_block(&mut heights, &mut hashes, &block, location)?;
+                        transactions.index(&block);
+                        offset += RECORD_HEADER_SIZE + len;
+                    }
+                    None if is_last => {
+                        warn!("Truncating torn record in {:?} at offset {} ({} bytes dropped)", path, offset, data.len() - offset);
+                        let file = OpenOptions::new().write(true).open(&path)?;
+                        file.set_len(offset as u64)?;
+                        file.sync_all()?;
+                        break;
+                    }
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Corrupted record in {:?} at offset {}", path, offset),
+                        ));
+                    }
+                }
+            }
+        }
+
+        let segment = segments.last().copied().unwrap_or(0);
+        let writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::segment_path(&dir, segment))?;
+        let segment_size = writer.metadata()?.len();
+
+        info!("Block store opened at {:?}: {} blocks", dir, heights.len());
+
+        Ok(FileBlockStore {
+            dir,
+            heights,
+            hashes,
+            transactions,
+            segment,
+            writer,
+            segment_size,
+        })
+    }
+
+    fn segment_path(dir: &Path, segment: u32) -> PathBuf {
+        dir.join(format!("segment_{:06}.dat", segment))
+    }
+
+    fn parse_segment_name(name: &str) -> Option<u32> {
+        name.strip_prefix("segment_")?.strip_suffix(".dat")?.parse().ok()
+    }
+
+    fn checksum(data: &[u8]) -> [u8; 4] {
+        let digest = Sha256::digest(data);
+        [digest[0], digest[1], digest[2], digest[3]]
+    }
+
+    // Разбирает запись в начале data. Возвращает блок и длину данных, либо None, если запись неполная или повреждена
+    fn decode_record(data: &[u8]) -> Option<(Block, usize)> {
+        if data.len() < RECORD_HEADER_SIZE {
+            return None;
+        }
+        let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let payload = data.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + len)?;
+        if Self::checksum(payload) != data[4..8] {
+            return None;
+        }
+        let block = serde_json::from_slice(payload).ok()?;
+        Some((block, len))
+    }
+
+    fn index_block(heights: &mut Vec<Location>, hashes: &mut HashMap<String, Location>, block: &Block, location: Location) -> io::Result<()> {
+        let height = block.header.index as usize;
+        if height > heights.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Block {} does not follow stored height {}", block.header.index, heights.len()),
+            ));
+        }
+        heights.truncate(height);
+        heights.push(location);
+        hashes.insert(block.hash.clone(), location);
+        Ok(())
+    }
+
+    fn read(&self, location: Location) -> io::Result<Block> {
+        let mut file = File::open(Self::segment_path(&self.dir, location.segment))?;
+        file.seek(SeekFrom::Start(location.offset + RECORD_HEADER_SIZE as u64))?;
+        let mut payload = vec![0; location.len as usize];
+        file.read_exact(&mut payload)?;
+        serde_json::from_slice(&payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Отрезает от сегмента недописанную запись после ошибки записи, чтобы смещения совпадали с файлом
+    fn discard_tail(&mut self) {
+        if let Err(e) = self.writer.set_len(self.segment_size) {
+            error!("Failed to truncate segment {} to {} bytes: {}", self.segment, self.segment_size, e);
+            // Дальнейшие записи должны начинаться с фактического конца файла
+            if let Ok(metadata) = self.writer.metadata() {
+                self.segment_size = metadata.len();
+            }
+        }
+    }
+
+    // Переходит на новый сегмент, если текущий заполнен
+    fn rotate_if_needed(&mut self) -> io::Result<()> {
+        if self.segment_size < SEGMENT_MAX_SIZE {
+            return Ok(());
+        }
+        self.segment += 1;
+        self.writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::segment_path(&self.dir, self.segment))?;
+        self.segment_size = 0;
+        Ok(())
+    }
+}
+
+impl BlockStore for FileBlockStore {
+    fn append_branch(&mut self, blocks: &[Block]) -> io::Result<()> {
+        check_branch(blocks, self.heights.len())?;
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        self.rotate_if_needed()?;
+
+        // Все записи ветки пишутся одним вызовом, индексы обновляются только после успешной записи
+        let mut records = Vec::new();
+        let mut locations = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            let payload = serde_json::to_vec(block).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            locations.push(Location {
+                segment: self.segment,
+                offset: self.segment_size + records.len() as u64,
+                len: payload.len() as u32,
+            });
+            records.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            records.extend_from_slice(&Self::checksum(&payload));
+            records.extend_from_slice(&payload);
+        }
+
+        if let Err(e) = self.writer.write_all(&records).and_then(|_| self.writer.sync_data()) {
+            self.discard_tail();
+            return Err(e);
+        }
+
+        self.segment_size += records.len() as u64;
+        for (block, location) in blocks.iter().zip(locations) {
+            Self::index_block(&mut self.heights, &mut self.hashes, block, location)?;
+            self.transactions.index(block);
+        }
+        Ok(())
+    }
+
+    fn get_by_height(&self, height: u64) -> io::Result<Option<Block>> {
+        match self.heights.get(height as usize) {
+            Some(&location) => self.read(location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn get_by_hash(&self, hash: &str) -> io::Result<Option<Block>> {
+        let location = match self.hashes.get(hash) {
+            Some(&location) => location,
+            None => return Ok(None),
+        };
+        // Индекс хешей содержит и блоки исключенных веток: блок должен стоять на своей высоте в основной цепочке
+        let block = self.read(location)?;
+        if self.heights.get(block.header.index as usize) != Some(&location) {
+            return Ok(None);
+        }
+        Ok(Some(block))
+    }
+
+    fn transaction_location(&self, hash: &str) -> Option<TransactionLocation> {
+        self.transactions.get(hash)
+    }
+
+    fn height(&self) -> Option<u64> {
+        (self.heights.len() as u64).checked_sub(1)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryBlockStore {
+    chain: Vec<Block>,
+    // Хеш блока основной цепочки -> высота
+    hashes: HashMap<String, u64>,
+    transactions: TransactionIndex,
+}
+
+impl MemoryBlockStore {
+    pub fn new() -> MemoryBlockStore {
+        MemoryBlockStore::default()
+    }
+}
+
+impl BlockStore for MemoryBlockStore {
+    fn append_branch(&mut self, blocks: &[Block]) -> io::Result<()> {
+        check_branch(blocks, self.chain.len())?;
+        for block in blocks {
+            for removed in self.chain.drain(block.header.index as usize..) {
+                self.hashes.remove(&removed.hash);
+            }
+            self.chain.push(block.clone());
+            self.hashes.insert(block.hash.clone(), block.header.index);
+            self.transactions.index(block);
+        }
+        Ok(())
+    }
+
+    fn get_by_height(&self, height: u64) -> io::Result<Option<Block>> {
+        Ok(self.chain.get(height as usize).cloned())
+    }
+
+    fn get_by_hash(&self, hash: &str) -> io::Result<Option<Block>> {
+        Ok(self.hashes.get(hash).and_then(|&height| self.chain.get(height as usize)).cloned())
+    }
+
+    fn transaction_location(&self, hash: &str) -> Option<TransactionLocation> {
+        self.transactions.get(hash)
+    }
+
+    fn height(&self) -> Option<u64> {
+        (self.chain.len() as u64).checked_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    fn block(index: u64, previous_hash: &str, nonces: &[u64]) -> Block {
+        let transactions = nonces
+            .iter()
+            .map(|&nonce| Transaction::new(String::from("alice"), String::from("bob"), 1, 1, index as u128, nonce))
+            .collect();
+        Block::new(index, previous_hash.to_string(), transactions, String::from("validator"), index)
+    }
+
+    // Проверяет индексы хешей и транзакций при смене ветки
+    fn check_indexes(store: &mut dyn BlockStore) {
+        let genesis = block(0, "", &[]);
+        let first = block(1, &genesis.hash, &[0, 1]);
+        let orphan = block(2, &first.hash, &[2]);
+        for block in [&genesis, &first, &orphan] {
+            store.append(block).unwrap();
+        }
+        let orphan_tx = orphan.transactions[0].hash.clone();
+        assert_eq!(store.transaction_location(&orphan_tx), Some(TransactionLocation { height: 2, position: 0 }));
+
+        let replacement = block(2, &first.hash, &[5, 6]);
+        store.append(&replacement).unwrap();
+
+        assert!(store.get_by_hash(&orphan.hash).unwrap().is_none());
+        assert_eq!(store.get_by_hash(&replacement.hash).unwrap().unwrap().hash, replacement.hash);
+        assert_eq!(store.transaction_location(&orphan_tx), None);
+        let second_tx = &first.transactions[1].hash;
+        assert_eq!(store.transaction_location(second_tx), Some(TransactionLocation { height: 1, position: 1 }));
+        let replaced_tx = &replacement.transactions[1].hash;
+        assert_eq!(store.transaction_location(replaced_tx), Some(TransactionLocation { height: 2, position: 1 }));
+    }
+
+    // Ветка с пропуском высоты отклоняется целиком, корректная ветка заменяет блоки основной цепочки
+    fn check_branch_switch(store: &mut dyn BlockStore) {
+        let genesis = block(0, "", &[]);
+        let first = block(1, &genesis.hash, &[0]);
+        let second = block(2, &first.hash, &[1]);
+        store.append_branch(&[genesis.clone(), first.clone(), second.clone()]).unwrap();
+
+        let side_first = block(1, &genesis.hash, &[2]);
+        let side_second = block(2, &side_first.hash, &[3]);
+        let side_fourth = block(4, &side_second.hash, &[4]);
+        assert!(store.append_branch(&[side_first.clone(), side_second.clone(), side_fourth]).is_err());
+        assert_eq!(store.height(), Some(2));
+        assert_eq!(store.get_by_height(2).unwrap().unwrap().hash, second.hash);
+        assert!(store.get_by_hash(&side_first.hash).unwrap().is_none());
+
+        let side_third = block(3, &side_second.hash, &[5]);
+        store.append_branch(&[side_first, side_second.clone(), side_third.clone()]).unwrap();
+        assert_eq!(store.height(), Some(3));
+        assert!(store.get_by_hash(&first.hash).unwrap().is_none());
+        assert_eq!(store.get_by_height(2).unwrap().unwrap().hash, side_second.hash);
+        assert_eq!(store.transaction_location(&second.transactions[0].hash), None);
+        assert_eq!(store.transaction_location(&side_third.transactions[0].hash), Some(TransactionLocation { height: 3, position: 0 }));
+    }
+
+    #[test]
+    fn memory_store_indexes_follow_main_chain() {
+        check_indexes(&mut MemoryBlockStore::new());
+    }
+
+    #[test]
+    fn branch_is_stored_whole_or_not_at_all() {
+        check_branch_switch(&mut MemoryBlockStore::new());
+
+        let dir = std::env::temp_dir().join(format!("oxion_store_branch_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        check_branch_switch(&mut FileBlockStore::open(&dir).unwrap());
+        let reopened = FileBlockStore::open(&dir).unwrap();
+        assert_eq!(reopened.height(), Some(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_store_indexes_follow_main_chain_and_survive_reopen() {
+        let dir = std::env::temp_dir().join(format!("oxion_store_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        check_indexes(&mut FileBlockStore::open(&dir).unwrap());
+
+        let reopened = FileBlockStore::open(&dir).unwrap();
+        assert_eq!(reopened.height(), Some(2));
+        let replacement = reopened.get_by_height(2).unwrap().unwrap();
+        let tx = &replacement.transactions[0].hash;
+        assert_eq!(reopened.transaction_location(tx), Some(TransactionLocation { height: 2, position: 0 }));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_store_truncates_torn_last_record() {
+        let dir = std::env::temp_dir().join(format!("oxion_store_torn_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let genesis = block(0, "", &[]);
+        let first = block(1, &genesis.hash, &[0]);
+        let second = block(2, &first.hash, &[1]);
+        {
+            let mut store = FileBlockStore::open(&dir).unwrap();
+            store.append(&genesis).unwrap();
+            store.append(&first).unwrap();
+        }
+
+        // Узел упал посреди записи второго блока: на диске остались заголовок и часть данных
+        let segment = FileBlockStore::segment_path(&dir, 0);
+        let good_size = fs::metadata(&segment).unwrap().len();
+        let payload = serde_json::to_vec(&second).unwrap();
+        let mut torn = (payload.len() as u32).to_le_bytes().to_vec();
+        torn.extend_from_slice(&FileBlockStore::checksum(&payload));
+        torn.extend_from_slice(&payload[..payload.len() / 2]);
+        OpenOptions::new().append(true).open(&segment).unwrap().write_all(&torn).unwrap();
+
+        let mut store = FileBlockStore::open(&dir).unwrap();
+        assert_eq!(store.height(), Some(1));
+        assert_eq!(fs::metadata(&segment).unwrap().len(), good_size);
+        store.append(&second).unwrap();
+        drop(store);
+
+        let reopened = FileBlockStore::open(&dir).unwrap();
+        assert_eq!(reopened.height(), Some(2));
+        assert_eq!(reopened.get_by_hash(&second.hash).unwrap().unwrap().hash, second.hash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
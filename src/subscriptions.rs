@@ -0,0 +1,252 @@
+/*
+    WebSocket подписки RPC сервера.
+    По адресу /ws принимаются запросы JSON-RPC 2.0 (без пакетов). Методы subscribe и unsubscribe управляют
+    подписками соединения, остальные методы выполняются так же, как через /rpc.
+    Виды подписок:
+    1. newHeads - хеш и заголовок каждого блока, добавленного в основную цепочку
+    2. newPendingTransactions - каждая новая транзакция мемпула
+    3. addressTransactions - транзакции, в которых адрес является отправителем или получателем:
+       при попадании в мемпул (pending) и при включении в блок основной цепочки (included)
+    subscribe возвращает идентификатор подписки, уведомления приходят в виде
+    {"jsonrpc": "2.0", "method": "subscription", "params": {"subscription": id, "result": ...}}
+*/
+use std::collections::HashMap;
+use actix_web::rt;
+use actix_web::web::{Data, Payload};
+use actix_web::{Error, HttpRequest, HttpResponse};
+use actix_ws::{Closed, Message as WsMessage, MessageStream, Session};
+use futures::StreamExt;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use crate::events::{ChainEvent, EventBus};
+use crate::rpc::{self, RpcError, RpcId, RpcRequest, RpcResponse, SubscribeParams, UnsubscribeParams};
+use crate::server::RPCServer;
+use crate::transaction::Transaction;
+
+// Максимальное количество подписок одного соединения
+const MAX_SUBSCRIPTIONS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Subscription {
+    NewHeads,
+    PendingTransactions,
+    Address(String),
+}
+
+impl Subscription {
+    fn from_params(params: SubscribeParams) -> Result<Subscription, RpcError> {
+        match (params.kind.as_str(), params.address) {
+            ("newHeads", None) => Ok(Subscription::NewHeads),
+            ("newPendingTransactions", None) => Ok(Subscription::PendingTransactions),
+            ("addressTransactions", Some(address)) => Ok(Subscription::Address(address)),
+            ("addressTransactions", None) => Err(RpcError::invalid_params("addressTransactions requires an address")),
+            ("newHeads", Some(_)) | ("newPendingTransactions", Some(_)) => {
+                Err(RpcError::invalid_params(format!("{} does not take an address", params.kind)))
+            }
+            (kind, _) => Err(RpcError::invalid_params(format!("unknown subscription {}", kind))),
+        }
+    }
+
+    // Уведомления подписки о событии. Блок может дать несколько уведомлений о транзакциях адреса
+    fn notifications(&self, event: &ChainEvent) -> Vec<Value> {
+        match (self, event) {
+            (Subscription::NewHeads, ChainEvent::NewBlock(block)) => {
+                vec![json!({ "hash": block.hash, "header": block.header })]
+            }
+            (Subscription::PendingTransactions, ChainEvent::PendingTransaction(tx)) => vec![json!(tx)],
+            (Subscription::Address(address), ChainEvent::PendingTransaction(tx)) if involves(tx, address) => {
+                vec![json!({ "status": "pending", "transaction": tx })]
+            }
+            (Subscription::Address(address), ChainEvent::NewBlock(block)) => block
+                .transactions
+                .iter()
+                .enumerate()
+                .filter(|(_, tx)| involves(tx, address))
+                .map(|(position, tx)| {
+                    json!({
+                        "status": "included",
+                        "transaction": tx,
+                        "block_height": block.header.index,
+                        "block_hash": block.hash,
+                        "position": position,
+                    })
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn involves(tx: &Transaction, address: &str) -> bool {
+    tx.addr == address || tx.to == address
+}
+
+// Одно WebSocket соединение и его подписки
+struct Connection {
+    session: Session,
+    server: Data<RPCServer>,
+    subscriptions: HashMap<String, Subscription>,
+    next_id: u64,
+}
+
+impl Connection {
+    async fn run(mut self, mut stream: MessageStream, mut events: broadcast::Receiver<ChainEvent>) {
+        let mut close_reason = None;
+        loop {
+            tokio::select! {
+                message = stream.next() => match message {
+                    Some(Ok(WsMessage::Text(text))) => {
+                        if self.on_text(&text).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(WsMessage::Ping(bytes))) => {
+                        if self.session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(WsMessage::Close(reason))) => {
+                        close_reason = reason;
+                        break;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        debug!("WebSocket protocol error: {}", e);
+                        break;
+                    }
+                    None => break,
+                },
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        if self.on_event(&event).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => warn!("WebSocket subscriber missed {} events", skipped),
+                    Err(RecvError::Closed) => break,
+                },
+            }
+        }
+        let _ = self.session.close(close_reason).await;
+    }
+
+    async fn on_text(&mut self, text: &str) -> Result<(), Closed> {
+        let value = match rpc::parse_json(text.as_bytes()) {
+            Ok(value) => value,
+            Err(e) => return self.send(&RpcResponse::new(RpcId::Null, Err(e))).await,
+        };
+        let request = match RpcRequest::parse(value) {
+            Ok(request) => request,
+            Err((id, error)) => return self.send(&RpcResponse::new(id, Err(error))).await,
+        };
+
+        let result = match request.method.as_str() {
+            "subscribe" => self.subscribe(&request),
+            "unsubscribe" => self.unsubscribe(&request),
+            _ => self.server.dispatch(&request).await,
+        };
+        match request.id {
+            Some(id) => self.send(&RpcResponse::new(id, result)).await,
+            None => Ok(()),
+        }
+    }
+
+    fn subscribe(&mut self, request: &RpcRequest) -> Result<Value, RpcError> {
+        let subscription = Subscription::from_params(request.params()?)?;
+        if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return Err(RpcError::new(
+                rpc::SUBSCRIPTION_LIMIT,
+                format!("Connection already has {} subscriptions", MAX_SUBSCRIPTIONS),
+            ));
+        }
+        self.next_id += 1;
+        let id = format!("0x{:x}", self.next_id);
+        self.subscriptions.insert(id.clone(), subscription);
+        Ok(json!(id))
+    }
+
+    // Возвращает true, если подписка была отменена, false - если такой подписки нет
+    fn unsubscribe(&mut self, request: &RpcRequest) -> Result<Value, RpcError> {
+        let params: UnsubscribeParams = request.params()?;
+        Ok(json!(self.subscriptions.remove(&params.subscription).is_some()))
+    }
+
+    async fn on_event(&mut self, event: &ChainEvent) -> Result<(), Closed> {
+        let mut messages = Vec::new();
+        for (id, subscription) in &self.subscriptions {
+            for result in subscription.notifications(event) {
+                messages.push(json!({
+                    "jsonrpc": rpc::JSONRPC_VERSION,
+                    "method": "subscription",
+                    "params": { "subscription": id, "result": result },
+                }));
+            }
+        }
+        for message in messages {
+            self.session.text(message.to_string()).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, response: &RpcResponse) -> Result<(), Closed> {
+        match serde_json::to_string(response) {
+            Ok(text) => self.session.text(text).await,
+            Err(e) => {
+                warn!("Failed to serialize RPC response: {}", e);
+                Ok(())
+            }
+        }
+    }
+}
+
+// Переводит HTTP запрос в WebSocket соединение и запускает его обработку
+pub async fn ws_handler(req: HttpRequest, body: Payload, server: Data<RPCServer>, events: Data<EventBus>) -> Result<HttpResponse, Error> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let connection = Connection {
+        session,
+        server: server.clone(),
+        subscriptions: HashMap::new(),
+        next_id: 0,
+    };
+    rt::spawn(connection.run(stream, events.subscribe()));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+
+    fn subscribe(kind: &str, address: Option<&str>) -> Result<Subscription, RpcError> {
+        Subscription::from_params(SubscribeParams { kind: kind.to_string(), address: address.map(str::to_string) })
+    }
+
+    #[test]
+    fn subscription_params_are_checked() {
+        assert_eq!(subscribe("newHeads", None).unwrap(), Subscription::NewHeads);
+        assert_eq!(subscribe("addressTransactions", Some("bob")).unwrap(), Subscription::Address(String::from("bob")));
+        assert_eq!(subscribe("addressTransactions", None).unwrap_err().code, rpc::INVALID_PARAMS);
+        assert_eq!(subscribe("newHeads", Some("bob")).unwrap_err().code, rpc::INVALID_PARAMS);
+        assert_eq!(subscribe("logs", None).unwrap_err().code, rpc::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn notifications_match_subscriptions() {
+        let to_bob = Transaction::new(String::from("alice"), String::from("bob"), 1, 1, 0, 0);
+        let to_carol = Transaction::new(String::from("alice"), String::from("carol"), 1, 1, 0, 1);
+        let block = Block::new(1, String::from("genesis"), vec![to_carol.clone(), to_bob.clone()], String::from("validator"), 1);
+        let bob = Subscription::Address(String::from("bob"));
+
+        assert_eq!(Subscription::NewHeads.notifications(&ChainEvent::NewBlock(block.clone())).len(), 1);
+        assert!(Subscription::NewHeads.notifications(&ChainEvent::PendingTransaction(to_bob.clone())).is_empty());
+        assert_eq!(Subscription::PendingTransactions.notifications(&ChainEvent::PendingTransaction(to_carol.clone())).len(), 1);
+
+        assert!(bob.notifications(&ChainEvent::PendingTransaction(to_carol)).is_empty());
+        assert_eq!(bob.notifications(&ChainEvent::PendingTransaction(to_bob))[0]["status"], "pending");
+        let included = bob.notifications(&ChainEvent::NewBlock(block));
+        assert_eq!(included.len(), 1);
+        assert_eq!((included[0]["status"].as_str(), included[0]["position"].as_u64()), (Some("included"), Some(1)));
+    }
+}
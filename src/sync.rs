@@ -0,0 +1,616 @@
+/*
+    Синхронизация цепочки с другими узлами.
+    Высоту, хеш последнего блока и вес цепочки узла сообщает его приветствие Hello при подключении.
+    Если у узла цепочка длиннее или тяжелее по правилу выбора основной цепочки,
+    у него запрашиваются заголовки пачками (GetHeaders -> Headers),
+    затем тела неизвестных блоков (GetBlocks -> Blocks). Полученные блоки проверяются и применяются
+    через Blockchain::import_block, после чего запрашивается следующая пачка заголовков.
+    Если первый заголовок пачки не продолжает известный блок (цепочки разошлись), запрос повторяется с меньшей высоты.
+    Если узел не отвечает дольше SYNC_TIMEOUT или присылает меньше, чем обещал, загрузка с ним прекращается.
+    За такие ответы и за сообщения, данные которых не разбираются, узел штрафуется.
+*/
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
+use log::{info, warn};
+use tcp_module::event::NetworkEvent;
+use tcp_module::message::{Message, MessageType};
+use tcp_module::reputation::{Misbehavior, Reputation};
+use crate::block::{Block, BlockHeader};
+use crate::blockchain::Blockchain;
+use crate::network::block_misbehavior;
+use crate::validation::ImportError;
+
+// Максимальное количество заголовков в одном ответе Headers
+pub const MAX_HEADERS_PER_MESSAGE: u64 = 64;
+// Максимальное количество блоков в одном ответе Blocks
+pub const MAX_BLOCKS_PER_MESSAGE: usize = 8;
+// Сколько узел синхронизации может не отвечать на запрос, прежде чем загрузка с ним прекратится
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusPayload {
+    pub genesis_hash: String,
+    pub best_height: u64,
+    pub best_hash: String,
+    #[serde(default)]
+    pub best_weight: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetHeadersPayload {
+    // Высота первого запрашиваемого заголовка
+    pub from: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderEntry {
+    pub hash: String,
+    pub header: BlockHeader,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeadersPayload {
+    pub headers: Vec<HeaderEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetBlocksPayload {
+    pub hashes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlocksPayload {
+    pub blocks: Vec<Block>,
+}
+
+struct PeerState {
+    // Очередь сообщений для этого узла
+    sender: Sender<Message>,
+    // Высота цепочки узла по его последнему статусу
+    best_height: u64,
+    // Вес цепочки узла по его последнему статусу
+    best_weight: u64,
+}
+
+// Текущая загрузка блоков от одного узла
+struct SyncSession {
+    peer: SocketAddr,
+    // Высота, с которой будет запрошена следующая пачка заголовков
+    next_from: u64,
+    // Количество запрошенных, но еще не полученных блоков
+    outstanding: usize,
+    // Время, до которого узел должен ответить. Сдвигается при каждом запросе и ответе
+    deadline: Instant,
+}
+
+pub struct SyncManager {
+    blockchain: Blockchain,
+    genesis_hash: String,
+    peers: HashMap<SocketAddr, PeerState>,
+    session: Option<SyncSession>,
+    reputation: Reputation,
+}
+
+/*
+    Проверяет, что заголовки из ответа Headers идут подряд и каждый ссылается на предыдущий.
+    Хеш каждого заголовка пересчитывается, полная проверка блока выполняется при импорте.
+*/
+pub fn check_headers(headers: &[HeaderEntry]) -> bool {
+    for (position, entry) in headers.iter().enumerate() {
+        if entry.header.calculate_hash() != entry.hash {
+            return false;
+        }
+        if position > 0 {
+            let previous = &headers[position - 1];
+            if entry.header.previous_hash != previous.hash || entry.header.index != previous.header.index + 1 {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+impl SyncManager {
+    pub async fn new(blockchain: Blockchain, reputation: Reputation) -> SyncManager {
+        let genesis_hash = blockchain.genesis_hash().await;
+        SyncManager {
+            blockchain,
+            genesis_hash,
+            peers: HashMap::new(),
+            session: None,
+            reputation,
+        }
+    }
+
+    // Разбирает данные сообщения, за некорректные данные узел штрафуется
+    async fn parse<T: DeserializeOwned>(&self, peer: SocketAddr, message: &Message) -> Option<T> {
+        match message.payload() {
+            Ok(payload) => Some(payload),
+            Err(e) => {
+                warn!("Message from {} rejected: {}", peer, e);
+                self.reputation.penalize(peer, Misbehavior::from(&e)).await;
+                None
+            }
+        }
+    }
+
+    // Обрабатывает событие сетевого модуля: подключение, отключение или сообщение синхронизации
+    pub async fn handle_event(&mut self, event: NetworkEvent) {
+        match event {
+            NetworkEvent::Connected { peer, hello, sender, .. } => {
+                self.peers.insert(peer, PeerState { sender, best_height: 0, best_weight: 0 });
+                let status = StatusPayload {
+                    genesis_hash: hello.genesis_hash,
+                    best_height: hello.best_height,
+                    best_hash: hello.best_hash,
+                    best_weight: hello.best_weight,
+                };
+                self.on_status(peer, status).await;
+            }
+            NetworkEvent::Disconnected { peer } => {
+                self.peers.remove(&peer);
+                if self.session.as_ref().map(|session| session.peer) == Some(peer) {
+                    info!("Sync peer {} disconnected", peer);
+                    self.session = None;
+                    self.start_sync().await;
+                }
+            }
+            NetworkEvent::Message { peer, message } => self.handle_message(peer, message).await,
+        }
+    }
+
+    fn send<T: Serialize>(&mut self, peer: SocketAddr, message_type: MessageType, payload: &T) {
+        let message = match serde_json::to_value(payload) {
+            Ok(data) => Message::new(message_type, data),
+            Err(e) => {
+                warn!("Failed to serialize sync message: {}", e);
+                return;
+            }
+        };
+        let sender = match self.peers.get(&peer) {
+            Some(state) => state.sender.clone(),
+            None => return,
+        };
+        // Ожидать место в очереди нельзя: соединение само может ждать обработки своих событий
+        match sender.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Queue of peer {} is full, sync message dropped", peer),
+            Err(TrySendError::Closed(_)) => {
+                self.peers.remove(&peer);
+            }
+        }
+    }
+
+    async fn handle_message(&mut self, peer: SocketAddr, message: Message) {
+        match message.message_type {
+            MessageType::Status => {
+                if let Some(status) = self.parse::<StatusPayload>(peer, &message).await {
+                    self.on_status(peer, status).await;
+                }
+            }
+            MessageType::GetHeaders => {
+                if let Some(request) = self.parse::<GetHeadersPayload>(peer, &message).await {
+                    let max = request.max.min(MAX_HEADERS_PER_MESSAGE) as usize;
+                    let headers = self
+                        .blockchain
+                        .headers(request.from, max)
+                        .await
+                        .into_iter()
+                        .map(|(hash, header)| HeaderEntry { hash, header })
+                        .collect();
+                    self.send(peer, MessageType::Headers, &HeadersPayload { headers });
+                }
+            }
+            MessageType::Headers => {
+                if let Some(response) = self.parse::<HeadersPayload>(peer, &message).await {
+                    self.on_headers(peer, response.headers).await;
+                }
+            }
+            MessageType::GetBlocks => {
+                if let Some(request) = self.parse::<GetBlocksPayload>(peer, &message).await {
+                    let mut hashes = request.hashes;
+                    hashes.truncate(MAX_HEADERS_PER_MESSAGE as usize);
+                    let blocks = self.blockchain.blocks_by_hash(&hashes).await;
+                    for chunk in blocks.chunks(MAX_BLOCKS_PER_MESSAGE) {
+                        self.send(peer, MessageType::Blocks, &BlocksPayload { blocks: chunk.to_vec() });
+                    }
+                }
+            }
+            MessageType::Blocks => {
+                if let Some(response) = self.parse::<BlocksPayload>(peer, &message).await {
+                    self.on_blocks(peer, response.blocks).await;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Узел прислал блок, который не продолжает известную цепочку: его цепочка не ниже height
+    pub async fn note_peer_height(&mut self, peer: SocketAddr, height: u64) {
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.best_height = state.best_height.max(height);
+        }
+        self.start_sync().await;
+    }
+
+    async fn on_status(&mut self, peer: SocketAddr, status: StatusPayload) {
+        if status.genesis_hash != self.genesis_hash {
+            warn!("Peer {} has a different genesis block {}", peer, status.genesis_hash);
+            self.peers.remove(&peer);
+            return;
+        }
+        let known = self.blockchain.knows(&status.best_hash).await;
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.best_height = status.best_height;
+            state.best_weight = status.best_weight;
+        }
+        if !known {
+            self.start_sync().await;
+        }
+    }
+
+    /*
+        Начинает загрузку от узла с самой тяжелой цепочкой, если она тяжелее или длиннее нашей.
+        Вес считается по правилу выбора основной цепочки, поэтому при HeaviestStake более тяжелая цепочка
+        может быть не длиннее нашей. Тогда заголовки запрашиваются с вершины узла, а общий предок
+        ищется ниже, как при расхождении цепочек.
+    */
+    async fn start_sync(&mut self) {
+        if self.session.is_some() {
+            return;
+        }
+        let (height, _) = self.blockchain.best().await;
+        let weight = self.blockchain.best_weight().await;
+        let best_peer = self
+            .peers
+            .iter()
+            .filter(|(_, state)| state.best_weight > weight || state.best_height > height)
+            .max_by_key(|(_, state)| (state.best_weight, state.best_height))
+            .map(|(peer, state)| (*peer, state.best_height, state.best_weight));
+
+        if let Some((peer, peer_height, peer_weight)) = best_peer {
+            info!(
+                "Syncing from {}: local height {} weight {}, peer height {} weight {}",
+                peer, height, weight, peer_height, peer_weight
+            );
+            let from = (height + 1).min(peer_height).max(1);
+            self.session = Some(SyncSession { peer, next_from: from, outstanding: 0, deadline: Instant::now() + SYNC_TIMEOUT });
+            self.request_headers(peer, from).await;
+        }
+    }
+
+    async fn request_headers(&mut self, peer: SocketAddr, from: u64) {
+        let request = GetHeadersPayload { from, max: MAX_HEADERS_PER_MESSAGE };
+        self.extend_deadline();
+        self.send(peer, MessageType::GetHeaders, &request);
+    }
+
+    fn extend_deadline(&mut self) {
+        if let Some(session) = self.session.as_mut() {
+            session.deadline = Instant::now() + SYNC_TIMEOUT;
+        }
+    }
+
+    // Прекращает загрузку с узлом, который не ответил, ответил не полностью или прислал неверные данные, и штрафует его
+    async fn abort_sync(&mut self, peer: SocketAddr, misbehavior: Misbehavior, reason: &str) {
+        warn!("Sync with {} aborted: {}", peer, reason);
+        self.reputation.penalize(peer, misbehavior).await;
+        // Заявленной высоте узла больше не верим до его следующего статуса
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.best_height = 0;
+        }
+        self.finish_sync().await;
+    }
+
+    // Прекращает загрузку, если узел синхронизации не ответил вовремя. Вызывается периодически
+    pub async fn check_timeout(&mut self, now: Instant) {
+        let peer = match &self.session {
+            Some(session) if session.deadline <= now => session.peer,
+            _ => return,
+        };
+        self.abort_sync(peer, Misbehavior::IncompleteResponse, "no response").await;
+    }
+
+    /*
+        Узел прислал все блоки до заявленной вершины. Его заявленные высота и вес дальше не учитываются,
+        иначе узел с более длинной, но более легкой цепочкой выбирался бы для загрузки снова и снова.
+    */
+    async fn complete_sync(&mut self, peer: SocketAddr) {
+        let (height, _) = self.blockchain.best().await;
+        let weight = self.blockchain.best_weight().await;
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.best_height = state.best_height.min(height);
+            state.best_weight = state.best_weight.min(weight);
+        }
+        self.finish_sync().await;
+    }
+
+    // Завершает загрузку. Если есть узлы с более длинной или тяжелой цепочкой, загрузка продолжается с ними
+    async fn finish_sync(&mut self) {
+        self.session = None;
+        let (height, _) = self.blockchain.best().await;
+        info!("Sync finished at height {}", height);
+        self.start_sync().await;
+    }
+
+    async fn on_headers(&mut self, peer: SocketAddr, headers: Vec<HeaderEntry>) {
+        if self.session.as_ref().map(|session| session.peer) != Some(peer) {
+            return;
+        }
+        if headers.is_empty() {
+            // Узел заявлял более длинную цепочку, но не прислал ни одного заголовка
+            let next_from = self.session.as_ref().map(|session| session.next_from).unwrap_or_default();
+            let peer_height = self.peers.get(&peer).map(|state| state.best_height).unwrap_or(0);
+            if next_from <= peer_height {
+                self.abort_sync(peer, Misbehavior::IncompleteResponse, "empty headers response").await;
+            } else {
+                self.complete_sync(peer).await;
+            }
+            return;
+        }
+        if !check_headers(&headers) {
+            self.abort_sync(peer, Misbehavior::InvalidBlock, "invalid header chain").await;
+            return;
+        }
+
+        // Первый заголовок не продолжает известный блок: ищем общего предка ниже
+        let first = &headers[0].header;
+        if !self.blockchain.knows(&first.previous_hash).await {
+            if first.index <= 1 {
+                warn!("Peer {} has no common ancestor with the local chain", peer);
+                self.complete_sync(peer).await;
+                return;
+            }
+            let from = first.index.saturating_sub(MAX_HEADERS_PER_MESSAGE).max(1);
+            if let Some(session) = self.session.as_mut() {
+                session.next_from = from;
+            }
+            self.request_headers(peer, from).await;
+            return;
+        }
+
+        let next_from = headers.last().map(|entry| entry.header.index + 1).unwrap_or_default();
+        let mut unknown = Vec::new();
+        for entry in headers {
+            if !self.blockchain.knows(&entry.hash).await {
+                unknown.push(entry.hash);
+            }
+        }
+
+        if let Some(session) = self.session.as_mut() {
+            session.next_from = next_from;
+            session.outstanding = unknown.len();
+        }
+        if unknown.is_empty() {
+            self.request_headers(peer, next_from).await;
+        } else {
+            self.extend_deadline();
+            self.send(peer, MessageType::GetBlocks, &GetBlocksPayload { hashes: unknown });
+        }
+    }
+
+    async fn on_blocks(&mut self, peer: SocketAddr, blocks: Vec<Block>) {
+        if self.session.as_ref().map(|session| session.peer) != Some(peer) {
+            return;
+        }
+
+        let received = blocks.len();
+        for block in blocks {
+            let index = block.header.index;
+            match self.blockchain.import_block(block).await {
+                Ok(()) | Err(ImportError::AlreadyKnown) => {}
+                Err(e) => {
+                    warn!("Block {} from {} rejected: {}", index, peer, e);
+                    // Неизвестный родитель или ошибка записи - не вина узла
+                    match block_misbehavior(&e) {
+                        Some(misbehavior) => self.abort_sync(peer, misbehavior, "invalid block").await,
+                        None => self.finish_sync().await,
+                    }
+                    return;
+                }
+            }
+        }
+
+        let (next_from, outstanding) = match self.session.as_mut() {
+            Some(session) => {
+                session.outstanding = session.outstanding.saturating_sub(received);
+                (session.next_from, session.outstanding)
+            }
+            None => return,
+        };
+        if outstanding > 0 {
+            // Блоки отправляются полными пачками, неполная пачка должна быть последней
+            if received < MAX_BLOCKS_PER_MESSAGE {
+                self.abort_sync(peer, Misbehavior::IncompleteResponse, "short blocks response").await;
+            } else {
+                self.extend_deadline();
+            }
+            return;
+        }
+
+        let peer_height = self.peers.get(&peer).map(|state| state.best_height).unwrap_or(0);
+        if next_from <= peer_height {
+            self.request_headers(peer, next_from).await;
+        } else {
+            self.complete_sync(peer).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use tokio::sync::mpsc::{self, Receiver};
+    use tcp_module::event::PEER_QUEUE_SIZE;
+    use tcp_module::hello::{Hello, PROTOCOL_VERSION};
+    use crate::storage::MemoryBlockStore;
+
+    const PEER_NODE: &str = "peer-node";
+
+    // Узел с одним генезисом подключается к узлу с цепочкой высоты best_height и начинает загрузку
+    async fn connected(best_height: u64) -> (SyncManager, Reputation, Receiver<Message>, SocketAddr) {
+        let (sync, reputation, mut outbox, peer) = connect(best_height, 0).await;
+        assert_eq!(outbox.try_recv().unwrap().message_type, MessageType::GetHeaders);
+        (sync, reputation, outbox, peer)
+    }
+
+    // Узел с одним генезисом подключается к узлу с цепочкой высоты best_height и веса best_weight
+    async fn connect(best_height: u64, best_weight: u64) -> (SyncManager, Reputation, Receiver<Message>, SocketAddr) {
+        let genesis = Blockchain::create_genesis_block(&BTreeMap::new());
+        let blockchain = Blockchain::for_tests(&genesis, "validator", Box::new(MemoryBlockStore::new()));
+        let reputation = Reputation::new(None);
+        let peer: SocketAddr = "127.0.0.1:30000".parse().unwrap();
+        reputation.connect(peer, PEER_NODE).await;
+        let mut sync = SyncManager::new(blockchain, reputation.clone()).await;
+
+        let (sender, outbox) = mpsc::channel(PEER_QUEUE_SIZE);
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: String::from("test"),
+            genesis_hash: genesis.hash,
+            best_height,
+            best_hash: String::from("unknown"),
+            best_weight,
+            node_id: PEER_NODE.to_string(),
+            listen_port: 30000,
+            capabilities: Vec::new(),
+        };
+        sync.handle_event(NetworkEvent::Connected { peer, node_id: PEER_NODE.to_string(), hello, sender }).await;
+        (sync, reputation, outbox, peer)
+    }
+
+    async fn deliver<T: Serialize>(sync: &mut SyncManager, peer: SocketAddr, message_type: MessageType, payload: &T) {
+        let message = Message::new(message_type, serde_json::to_value(payload).unwrap());
+        sync.handle_event(NetworkEvent::Message { peer, message }).await;
+    }
+
+    async fn score(reputation: &Reputation, peer: SocketAddr) -> i32 {
+        reputation.peer_scores().await.iter().find(|score| score.address == peer).unwrap().score
+    }
+
+    #[tokio::test]
+    async fn silent_sync_peer_times_out() {
+        let (mut sync, reputation, mut outbox, peer) = connected(5).await;
+        sync.check_timeout(Instant::now()).await;
+        assert!(sync.session.is_some());
+
+        sync.check_timeout(Instant::now() + SYNC_TIMEOUT).await;
+        assert!(sync.session.is_none());
+        assert!(score(&reputation, peer).await < 0);
+        // До нового статуса узел не выбирается для загрузки повторно
+        assert!(outbox.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn heavier_chain_of_same_height_is_synced() {
+        // Вес генезиса 0, цепочка узла той же высоты, но тяжелее
+        let (mut sync, _reputation, mut outbox, peer) = connect(0, 1).await;
+        let request: GetHeadersPayload = outbox.try_recv().unwrap().payload().unwrap();
+        assert_eq!(request.from, 1);
+
+        // Узел не прислал более тяжелых блоков: повторно он не выбирается
+        deliver(&mut sync, peer, MessageType::Headers, &HeadersPayload { headers: Vec::new() }).await;
+        assert!(sync.session.is_none());
+        assert!(outbox.try_recv().is_err());
+
+        let (sync, _reputation, mut outbox, _peer) = connect(0, 0).await;
+        assert!(sync.session.is_none());
+        assert!(outbox.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn short_replies_end_sync() {
+        let (mut sync, reputation, mut outbox, peer) = connected(20).await;
+        let headers = header_chain(20).split_off(1);
+        deliver(&mut sync, peer, MessageType::Headers, &HeadersPayload { headers }).await;
+        let request: GetBlocksPayload = outbox.try_recv().unwrap().payload().unwrap();
+        assert_eq!(request.hashes.len(), 19);
+        assert_eq!(sync.session.as_ref().map(|session| session.outstanding), Some(19));
+
+        // Вместо трех пачек блоков пришла одна неполная
+        deliver(&mut sync, peer, MessageType::Blocks, &BlocksPayload { blocks: Vec::new() }).await;
+        assert!(sync.session.is_none());
+        assert!(score(&reputation, peer).await < 0);
+
+        let (mut sync, reputation, _outbox, peer) = connected(20).await;
+        deliver(&mut sync, peer, MessageType::Headers, &HeadersPayload { headers: Vec::new() }).await;
+        assert!(sync.session.is_none());
+        assert!(score(&reputation, peer).await < 0);
+    }
+
+    #[tokio::test]
+    async fn invalid_sync_data_is_penalized() {
+        let (mut sync, reputation, _outbox, peer) = connected(5).await;
+        let mut headers = header_chain(5).split_off(1);
+        headers.remove(1);
+        deliver(&mut sync, peer, MessageType::Headers, &HeadersPayload { headers }).await;
+        assert!(sync.session.is_none());
+        assert_eq!(score(&reputation, peer).await, -Misbehavior::InvalidBlock.penalty());
+
+        // Блок без подписи валидатора - нарушение, блок с неизвестным родителем - нет
+        let (mut sync, reputation, mut outbox, peer) = connected(5).await;
+        let headers = header_chain(5).split_off(1);
+        deliver(&mut sync, peer, MessageType::Headers, &HeadersPayload { headers }).await;
+        outbox.try_recv().unwrap();
+        let validator = crate::keys::test_keypair(5);
+        let mut orphan = Block::new(3, String::from("unknown"), Vec::new(), crate::keys::address(&validator.public), 3);
+        orphan.sign(&validator);
+        deliver(&mut sync, peer, MessageType::Blocks, &BlocksPayload { blocks: vec![orphan] }).await;
+        assert_eq!(score(&reputation, peer).await, 0);
+
+        let (mut sync, reputation, mut outbox, peer) = connected(5).await;
+        let mut headers = header_chain(5).split_off(1);
+        deliver(&mut sync, peer, MessageType::Headers, &HeadersPayload { headers: headers.clone() }).await;
+        outbox.try_recv().unwrap();
+        let first = headers.remove(0);
+        let unsigned = Block::new(1, first.header.previous_hash, Vec::new(), String::from("v"), 1);
+        deliver(&mut sync, peer, MessageType::Blocks, &BlocksPayload { blocks: vec![unsigned] }).await;
+        assert!(sync.session.is_none());
+        assert_eq!(score(&reputation, peer).await, -Misbehavior::InvalidBlock.penalty());
+    }
+
+    fn header_chain(length: u64) -> Vec<HeaderEntry> {
+        let genesis = Blockchain::create_genesis_block(&BTreeMap::new());
+        let mut entries = vec![HeaderEntry { hash: genesis.hash.clone(), header: genesis.header }];
+        for index in 1..length {
+            let previous = entries.last().unwrap().hash.clone();
+            let block = Block::new(index, previous, Vec::new(), String::from("v"), index);
+            entries.push(HeaderEntry { hash: block.hash, header: block.header });
+        }
+        entries
+    }
+
+    #[test]
+    fn linked_headers_are_accepted() {
+        assert!(check_headers(&header_chain(5)));
+        assert!(check_headers(&[]));
+    }
+
+    #[test]
+    fn broken_headers_are_rejected() {
+        let mut gap = header_chain(5);
+        gap.remove(2);
+        assert!(!check_headers(&gap));
+
+        let mut forged = header_chain(3);
+        forged[1].header.slot += 1;
+        assert!(!check_headers(&forged));
+    }
+
+    #[test]
+    fn genesis_is_deterministic() {
+        let mut balances = BTreeMap::new();
+        balances.insert(String::from("alice"), 100);
+        assert_eq!(
+            Blockchain::create_genesis_block(&balances).hash,
+            Blockchain::create_genesis_block(&balances).hash
+        );
+    }
+}
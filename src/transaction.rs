@@ -1,30 +1,35 @@
 // src/transaction.rs
 use serde::{Serialize, Deserialize};
 use std::collections::BinaryHeap;
-use std::sync::Arc;
 use log::info;
-use crossbeam::channel::Receiver;
-use std::collections::HashSet;
-use sha2::Sha256;
-use sha2::Digest;
-use std::time::UNIX_EPOCH;
-use std::time::SystemTime;
-use tokio::sync::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::cmp::Ordering;
+use crate::encoding::{hash_hex, Encoder, TRANSACTION_DOMAIN};
+use crate::state::{StateError, WorldState};
+use crate::keys;
+use crate::events::{ChainEvent, EventBus};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Transaction {
     pub addr: String,
     pub to: String,
+    // Сумма в JSON передается десятичной строкой
+    #[serde(with = "crate::amount")]
     pub amount: u128,
     pub timestamp: u128,
     pub fee: u64,
+    // Порядковый номер транзакции отправителя. Каждый номер может быть исполнен только один раз
+    pub nonce: u64,
     pub hash: String,
+    // Подпись отправителя (base64) над каноническим кодированием транзакции. В хеш не входит
+    #[serde(default)]
+    pub signature: String,
 }
 
 impl Transaction {
-    pub fn new(addr: String, to: String, amount: u128, fee: u64, timestamp: u128) -> Transaction {
-        let hash = Self::calculate_hash(&addr, &to, amount, timestamp, fee);
+    pub fn new(addr: String, to: String, amount: u128, fee: u64, timestamp: u128, nonce: u64) -> Transaction {
+        let hash = Self::calculate_hash(&addr, &to, amount, timestamp, fee, nonce);
         
         Transaction {
             addr, 
@@ -32,25 +37,44 @@ impl Transaction {
             amount,
             timestamp, 
             fee,
+            nonce,
             hash,
+            signature: String::new(),
         }
     }
 
-    pub fn calculate_hash(addr: &str, to: &str, amount: u128, timestamp: u128, fee: u64) -> String {
-        let input = format!("{}{}{}{}{}", addr, to, amount, timestamp, fee);
-        let mut hasher = Sha256::new();
-        hasher.update(input);
-        let result = hasher.finalize();
-        format!("{:x}", result)
+    pub fn with_signature(mut self, signature: String) -> Transaction {
+        self.signature = signature;
+        self
+    }
+
+    // Проверяет хеш транзакции и подпись ключом отправителя
+    pub fn verify(&self) -> bool {
+        let bytes = Self::signing_bytes(&self.addr, &self.to, self.amount, self.timestamp, self.fee, self.nonce);
+        hash_hex(&bytes) == self.hash && keys::verify(&self.addr, &bytes, &self.signature)
+    }
+
+    // Проверяет только хеш. Используется для наград от сети, которые не подписываются
+    pub fn has_valid_hash(&self) -> bool {
+        Self::calculate_hash(&self.addr, &self.to, self.amount, self.timestamp, self.fee, self.nonce) == self.hash
     }
-}
 
-// impl Ord for Transaction {
-//     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-//         // Reverse позволяет нам сортировать в порядке убывания
-//         self.fee.cmp(&other.fee).then_with(|| self.timestamp.cmp(&other.timestamp))
-//     }
-// }
+    // Каноническое кодирование транзакции. Эти байты хешируются и подписываются отправителем
+    pub fn signing_bytes(addr: &str, to: &str, amount: u128, timestamp: u128, fee: u64, nonce: u64) -> Vec<u8> {
+        Encoder::new(TRANSACTION_DOMAIN)
+            .str(addr)
+            .str(to)
+            .u128(amount)
+            .u128(timestamp)
+            .u64(fee)
+            .u64(nonce)
+            .finish()
+    }
+
+    pub fn calculate_hash(addr: &str, to: &str, amount: u128, timestamp: u128, fee: u64, nonce: u64) -> String {
+        hash_hex(&Self::signing_bytes(addr, to, amount, timestamp, fee, nonce))
+    }
+}
 
 impl Ord for Transaction {
     fn cmp(&self, other: &Self) -> Ordering {
@@ -71,9 +95,74 @@ impl PartialOrd for Transaction {
     }
 }
 
+#[derive(Debug)]
+pub enum MempoolError {
+    // Транзакция с таким хешем уже находится в мемпуле
+    Duplicate,
+    // В мемпуле уже есть транзакция отправителя с таким nonce
+    NonceInUse,
+    // Транзакция не проходит проверку по состоянию аккаунтов
+    State(StateError),
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Duplicate => write!(f, "Transaction already in mempool"),
+            MempoolError::NonceInUse => write!(f, "Nonce already used by a pending transaction"),
+            MempoolError::State(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Ограничения размера блока
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLimits {
+    // Максимальное количество транзакций в блоке (без награды валидатора)
+    pub max_transactions: usize,
+    // Максимальный суммарный размер транзакций в байтах (в кодировке JSON, в которой блок передается по сети)
+    pub max_bytes: usize,
+    // Максимальный суммарный вес транзакций. Вес транзакции - ее комиссия. None - без ограничения
+    pub max_fee_weight: Option<u64>,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        BlockLimits {
+            max_transactions: 1000,
+            max_bytes: 1024 * 1024,
+            max_fee_weight: None,
+        }
+    }
+}
+
+// Сколько последних выброшенных из мемпула транзакций помнить для getTransactionStatus
+const MAX_DROPPED: usize = 10_000;
+
+// Статистика мемпула. Комиссии - None, если мемпул пуст
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MempoolStats {
+    pub count: usize,
+    // Суммарный размер транзакций в кодировке JSON
+    pub bytes: usize,
+    pub min_fee: Option<u64>,
+    // Для четного количества - меньшая из двух средних комиссий
+    pub median_fee: Option<u64>,
+    pub max_fee: Option<u64>,
+}
+
 pub struct Mempool {
     transactions: BinaryHeap<Transaction>,
     tx_hashes: HashSet<String>,
+    // Сумма amount + fee всех транзакций отправителя, ожидающих в мемпуле
+    pending_spend: HashMap<String, u128>,
+    // Nonce отправителей, занятые транзакциями в мемпуле
+    pending_nonces: HashSet<(String, u64)>,
+    // Транзакции, покинувшие мемпул без включения в блок: хеш -> причина. Старые записи вытесняются
+    dropped: HashMap<String, String>,
+    dropped_order: VecDeque<String>,
+    // Шина событий, в которую сообщается о новых транзакциях
+    events: EventBus,
 }
 
 impl Mempool {
@@ -81,66 +170,239 @@ impl Mempool {
         Mempool {
             transactions: BinaryHeap::new(),
             tx_hashes: HashSet::new(),
+            pending_spend: HashMap::new(),
+            pending_nonces: HashSet::new(),
+            dropped: HashMap::new(),
+            dropped_order: VecDeque::new(),
+            events: EventBus::new(),
         }
     }
 
-    // pub async fn run(&mut self) {
-    //     let receiver = self.receiver.clone();
-
-    //     loop {
-    //         let _tx_hashes = self.tx_hashes.lock().await;
-    //         for _transaction in &receiver {
-    //             // if tx_hashes.contains(&transaction.hash) {
-    //             //     println!("Транзакция уже содержится");
-    //             //     continue;
-    //             // }
-    //             // self.add_transaction(transaction).await;
-    //             info!("Транзакция получена.");
-    //         }
-    //     }
-    // }
-
-    pub fn add_transaction(&mut self, tx: Transaction) -> bool {
-        match self.tx_hashes.insert(tx.hash.clone()) {
-            true => {
-                self.transactions.push(tx);
-                info!("Tx in mempool: {}", self.transactions.len());
-                println!("{}", self.tx_hashes.len());
-                return true;
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    // Добавляет транзакцию, если отправитель может оплатить ее вместе с уже ожидающими транзакциями
+    pub fn add_transaction(&mut self, tx: Transaction, state: &WorldState) -> Result<(), MempoolError> {
+        self.insert(tx.clone(), state)?;
+        self.events.emit(ChainEvent::PendingTransaction(tx));
+        Ok(())
+    }
+
+    // Возвращает в мемпул транзакцию, которую забрали для блока, но не включили в него. О ней уже сообщалось подписчикам
+    pub fn return_transaction(&mut self, tx: Transaction, state: &WorldState) -> Result<(), MempoolError> {
+        self.insert(tx, state)
+    }
+
+    fn insert(&mut self, tx: Transaction, state: &WorldState) -> Result<(), MempoolError> {
+        if self.tx_hashes.contains(&tx.hash) {
+            return Err(MempoolError::Duplicate);
+        }
+        if self.pending_nonces.contains(&(tx.addr.clone(), tx.nonce)) {
+            return Err(MempoolError::NonceInUse);
+        }
+
+        let pending = self.pending_spend.get(&tx.addr).copied().unwrap_or(0);
+        state.check_transaction(&tx, pending).map_err(MempoolError::State)?;
+        let cost = WorldState::transaction_cost(&tx).map_err(MempoolError::State)?;
+
+        self.pending_spend.insert(tx.addr.clone(), pending + cost);
+        self.pending_nonces.insert((tx.addr.clone(), tx.nonce));
+        self.tx_hashes.insert(tx.hash.clone());
+        self.dropped.remove(&tx.hash);
+        self.transactions.push(tx);
+        info!("Tx in mempool: {}", self.transactions.len());
+        Ok(())
+    }
+
+    // Снимает резерв отправителя после того, как транзакция покинула мемпул
+    fn release(&mut self, tx: &Transaction) {
+        self.pending_nonces.remove(&(tx.addr.clone(), tx.nonce));
+        let cost = WorldState::transaction_cost(tx).unwrap_or(0);
+        if let Some(pending) = self.pending_spend.get_mut(&tx.addr) {
+            *pending = pending.saturating_sub(cost);
+            if *pending == 0 {
+                self.pending_spend.remove(&tx.addr);
             }
-            false => {
+        }
+    }
+
+    // Удаляет транзакцию из мемпула, например, после того как она попала в блок от другого узла
+    pub fn remove_transaction(&mut self, tx_id: &str) -> Option<Transaction> {
+        if !self.tx_hashes.remove(tx_id) {
+            return None;
+        }
+
+        let mut removed_transaction = None;
+        self.transactions.retain(|tx| {
+            if tx.hash == tx_id {
+                removed_transaction = Some(tx.clone());
                 return false;
             }
+            true
+        });
+
+        if let Some(ref tx) = removed_transaction {
+            self.release(tx);
         }
+        removed_transaction
     }
 
-    async fn remove_transaction(&mut self, tx_id: &str) -> Option<Transaction> {
-        let mut temp_heap = BinaryHeap::new();
-        let mut removed_transaction = None;
+    pub fn contains(&self, hash: &str) -> bool {
+        self.tx_hashes.contains(hash)
+    }
 
-        while let Some(tx) = self.transactions.pop() {
-            if tx.hash == tx_id {
-                removed_transaction = Some(tx);
-                break;
-            } else {
-                temp_heap.push(tx);
+    // Все ожидающие транзакции в порядке включения в блок: по убыванию комиссии
+    pub fn get_all_transactions(&self) -> Vec<Transaction> {
+        let mut transactions = self.transactions.clone().into_sorted_vec();
+        transactions.reverse();
+        transactions
+    }
+
+    pub fn stats(&self) -> MempoolStats {
+        let mut fees: Vec<u64> = self.transactions.iter().map(|tx| tx.fee).collect();
+        fees.sort_unstable();
+        let bytes = self
+            .transactions
+            .iter()
+            .map(|tx| serde_json::to_vec(tx).map(|encoded| encoded.len()).unwrap_or(0))
+            .sum();
+        MempoolStats {
+            count: fees.len(),
+            bytes,
+            min_fee: fees.first().copied(),
+            median_fee: fees.get(fees.len().saturating_sub(1) / 2).copied(),
+            max_fee: fees.last().copied(),
+        }
+    }
+
+    // Запоминает транзакцию, которая покинула мемпул и не попала в блок
+    pub fn record_dropped(&mut self, hash: &str, reason: impl ToString) {
+        if self.dropped.insert(hash.to_string(), reason.to_string()).is_none() {
+            self.dropped_order.push_back(hash.to_string());
+        }
+        while self.dropped_order.len() > MAX_DROPPED {
+            if let Some(oldest) = self.dropped_order.pop_front() {
+                self.dropped.remove(&oldest);
             }
         }
+    }
 
-        self.transactions = temp_heap;
-        removed_transaction
+    // Причина, по которой транзакция была выброшена из мемпула
+    pub fn dropped_reason(&self, hash: &str) -> Option<&str> {
+        self.dropped.get(hash).map(String::as_str)
     }
 
-    pub async fn get_all_transactions(&self) -> Vec<Transaction> {
-        self.transactions.iter().cloned().collect()
+    /*
+        Забирает из мемпула транзакции для блока по убыванию комиссии, пока они помещаются в ограничения limits.
+        Транзакция, которая не помещается по размеру или весу, остается в мемпуле, а выбор продолжается
+        со следующей: меньшая транзакция еще может поместиться.
+    */
+    pub fn take_batch(&mut self, limits: &BlockLimits) -> Vec<Transaction> {
+        let mut batch = Vec::new();
+        let mut skipped = Vec::new();
+        let mut bytes = 0usize;
+        let mut weight = 0u64;
+
+        while batch.len() < limits.max_transactions {
+            let tx = match self.transactions.pop() {
+                Some(tx) => tx,
+                None => break,
+            };
+            let size = serde_json::to_vec(&tx).map(|encoded| encoded.len()).unwrap_or(usize::MAX);
+            let fits_bytes = bytes.checked_add(size).map(|total| total <= limits.max_bytes).unwrap_or(false);
+            let fits_weight = match limits.max_fee_weight {
+                Some(max) => weight.checked_add(tx.fee).map(|total| total <= max).unwrap_or(false),
+                None => true,
+            };
+            if !fits_bytes || !fits_weight {
+                skipped.push(tx);
+                continue;
+            }
+
+            bytes += size;
+            weight = weight.saturating_add(tx.fee);
+            self.tx_hashes.remove(&tx.hash);
+            self.release(&tx);
+            batch.push(tx);
+        }
+
+        self.transactions.extend(skipped);
+        batch
     }
 
+    #[allow(dead_code)]
     pub fn get_highest_fee_transaction(&mut self) -> Option<Transaction> {
         let transaction = self.transactions.pop();
         if let Some(ref tx) = transaction {
             self.tx_hashes.remove(&tx.hash);
+            self.release(tx);
         }
 
         transaction
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::blockchain::Blockchain;
+
+    fn mempool_with(fees: &[u64]) -> Mempool {
+        let mut balances = BTreeMap::new();
+        balances.insert(String::from("alice"), 1_000_000);
+        let state = WorldState::from_blocks(&[Blockchain::create_genesis_block(&balances)]).unwrap();
+        let mut mempool = Mempool::new();
+        for (nonce, fee) in fees.iter().enumerate() {
+            let tx = Transaction::new(String::from("alice"), String::from("bob"), 1, *fee, 0, nonce as u64);
+            mempool.add_transaction(tx, &state).unwrap();
+        }
+        mempool
+    }
+
+    #[test]
+    fn batch_is_limited_by_count() {
+        let mut mempool = mempool_with(&[1, 5, 3, 4]);
+        let limits = BlockLimits { max_transactions: 2, ..BlockLimits::default() };
+        let fees: Vec<u64> = mempool.take_batch(&limits).iter().map(|tx| tx.fee).collect();
+        assert_eq!(fees, vec![5, 4]);
+        assert_eq!(mempool.take_batch(&limits).len(), 2);
+        assert!(mempool.take_batch(&limits).is_empty());
+    }
+
+    #[test]
+    fn oversized_transactions_stay_in_mempool() {
+        let mut mempool = mempool_with(&[10, 6, 3]);
+        // Вес 10 не помещается, 6 и 3 вместе помещаются
+        let limits = BlockLimits { max_fee_weight: Some(9), ..BlockLimits::default() };
+        let fees: Vec<u64> = mempool.take_batch(&limits).iter().map(|tx| tx.fee).collect();
+        assert_eq!(fees, vec![6, 3]);
+
+        let limits = BlockLimits { max_bytes: 10, ..BlockLimits::default() };
+        assert!(mempool.take_batch(&limits).is_empty());
+        assert_eq!(mempool.take_batch(&BlockLimits::default()).len(), 1);
+    }
+
+    #[test]
+    fn stats_and_dropped_transactions() {
+        let mut mempool = mempool_with(&[4, 1, 9, 2]);
+        let stats = mempool.stats();
+        assert_eq!((stats.count, stats.min_fee, stats.median_fee, stats.max_fee), (4, Some(1), Some(2), Some(9)));
+        assert!(stats.bytes > 0);
+        let fees: Vec<u64> = mempool.get_all_transactions().iter().map(|tx| tx.fee).collect();
+        assert_eq!(fees, vec![9, 4, 2, 1]);
+        assert_eq!(Mempool::new().stats().median_fee, None);
+
+        let tx = mempool.take_batch(&BlockLimits { max_transactions: 1, ..BlockLimits::default() }).remove(0);
+        assert!(!mempool.contains(&tx.hash));
+        mempool.record_dropped(&tx.hash, "Balance overflow");
+        assert_eq!(mempool.dropped_reason(&tx.hash), Some("Balance overflow"));
+
+        for i in 0..MAX_DROPPED {
+            mempool.record_dropped(&i.to_string(), "old");
+        }
+        assert_eq!(mempool.dropped_reason(&tx.hash), None);
+        assert_eq!(mempool.dropped.len(), MAX_DROPPED);
+    }
+}
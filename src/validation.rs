@@ -0,0 +1,230 @@
+/*
+    Проверка блоков, полученных от других узлов.
+    Проверки разделены на две части:
+    1. check_block - проверки самого блока, не требующие цепочки: хеш, корень транзакций, подписи
+    2. check_block_context - проверки относительно родительского блока: связь, номер, время, лидер слота
+    Изменение состояния проверяется отдельно при применении блока к WorldState.
+*/
+use std::fmt;
+use serde::Serialize;
+use crate::block::Block;
+use crate::pos::PoS;
+use crate::state::{StateError, NETWORK_ADDRESS};
+
+// Насколько время блока может опережать локальное время узла, мс
+pub const MAX_FUTURE_DRIFT_MS: u128 = 15_000;
+
+// Причина отклонения блока. Может быть отправлена узлу, приславшему блок
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "error", content = "details")]
+pub enum ImportError {
+    // Не удалось разобрать блок из сообщения
+    Malformed(String),
+    // Блок уже есть в цепочке
+    AlreadyKnown,
+    // Родительский блок неизвестен
+    UnknownParent { previous_hash: String },
+    InvalidIndex { expected: u64, got: u64 },
+    InvalidTimestamp(String),
+    InvalidHash,
+    InvalidTransactionsRoot,
+    // Слот не больше слота родителя или не соответствует времени блока
+    InvalidSlot,
+    WrongLeader { validator: String },
+    InvalidSignature,
+    InvalidTransaction { hash: String, reason: String },
+    State(StateError),
+    Storage(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Malformed(e) => write!(f, "Malformed block: {}", e),
+            ImportError::AlreadyKnown => write!(f, "Block already known"),
+            ImportError::UnknownParent { previous_hash } => write!(f, "Unknown parent block {}", previous_hash),
+            ImportError::InvalidIndex { expected, got } => write!(f, "Invalid block index: expected {}, got {}", expected, got),
+            ImportError::InvalidTimestamp(reason) => write!(f, "Invalid block timestamp: {}", reason),
+            ImportError::InvalidHash => write!(f, "Invalid block hash"),
+            ImportError::InvalidTransactionsRoot => write!(f, "Invalid transactions root"),
+            ImportError::InvalidSlot => write!(f, "Invalid block slot"),
+            ImportError::WrongLeader { validator } => write!(f, "Validator {} is not the slot leader", validator),
+            ImportError::InvalidSignature => write!(f, "Invalid block signature"),
+            ImportError::InvalidTransaction { hash, reason } => write!(f, "Invalid transaction {}: {}", hash, reason),
+            ImportError::State(e) => write!(f, "{}", e),
+            ImportError::Storage(e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+// Проверки блока, не зависящие от цепочки. now - локальное время узла в мс
+pub fn check_block(block: &Block, now: u128) -> Result<(), ImportError> {
+    if block.header.timestamp > now + MAX_FUTURE_DRIFT_MS {
+        return Err(ImportError::InvalidTimestamp("block is from the future".to_string()));
+    }
+    if block.hash != block.header.calculate_hash() {
+        return Err(ImportError::InvalidHash);
+    }
+    if block.header.transactions_root != Block::calculate_transactions_root(&block.transactions) {
+        return Err(ImportError::InvalidTransactionsRoot);
+    }
+    if !block.verify_signature() {
+        return Err(ImportError::InvalidSignature);
+    }
+    // Награда от сети не подписывается, у нее проверяется только хеш. Сумма и получатель проверяются при применении к состоянию
+    for tx in &block.transactions {
+        let valid = if tx.addr == NETWORK_ADDRESS { tx.has_valid_hash() } else { tx.verify() };
+        if !valid {
+            return Err(ImportError::InvalidTransaction {
+                hash: tx.hash.clone(),
+                reason: "invalid hash or signature".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Проверки блока относительно родителя
+pub fn check_block_context(block: &Block, parent: &Block, pos: &PoS) -> Result<(), ImportError> {
+    if block.header.previous_hash != parent.hash {
+        return Err(ImportError::UnknownParent { previous_hash: block.header.previous_hash.clone() });
+    }
+    if block.header.index != parent.header.index + 1 {
+        return Err(ImportError::InvalidIndex {
+            expected: parent.header.index + 1,
+            got: block.header.index,
+        });
+    }
+    if block.header.timestamp <= parent.header.timestamp {
+        return Err(ImportError::InvalidTimestamp("block is older than its parent".to_string()));
+    }
+    if block.header.slot <= parent.header.slot || pos.slot_at(block.header.timestamp) != block.header.slot {
+        return Err(ImportError::InvalidSlot);
+    }
+    if !pos.verify_leader(&block.header, &parent.hash) {
+        return Err(ImportError::WrongLeader { validator: block.header.validator.clone() });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Keypair;
+    use crate::keys;
+    use crate::state::BLOCK_REWARD;
+    use crate::transaction::Transaction;
+
+    const SLOT_MS: u128 = 1000;
+    const NOW: u128 = 10 * SLOT_MS;
+
+    fn parent() -> Block {
+        let mut genesis = Block::new(0, String::from("0"), Vec::new(), String::new(), 0);
+        genesis.header.timestamp = 0;
+        genesis.hash = genesis.header.calculate_hash();
+        genesis
+    }
+
+    // Пересчитывает хеш и подпись после изменения заголовка
+    fn seal(mut block: Block, validator: &Keypair) -> Block {
+        block.hash = block.header.calculate_hash();
+        block.sign(validator);
+        block
+    }
+
+    // Блок с переводом от sender и наградой, созданный validator в слоте slot
+    fn child(parent: &Block, validator: &Keypair, slot: u64) -> Block {
+        let sender = keys::test_keypair(9);
+        let addr = keys::address(&sender.public);
+        let signature = keys::sign(&sender, &Transaction::signing_bytes(&addr, "bob", 1, 0, 1, 0));
+        let address = keys::address(&validator.public);
+        let transactions = vec![
+            Transaction::new(addr, String::from("bob"), 1, 1, 0, 0).with_signature(signature),
+            Transaction::new(NETWORK_ADDRESS.to_string(), address.clone(), BLOCK_REWARD, 0, 0, parent.header.index + 1),
+        ];
+        let mut block = Block::new(parent.header.index + 1, parent.hash.clone(), transactions, address, slot);
+        block.header.timestamp = slot as u128 * SLOT_MS;
+        seal(block, validator)
+    }
+
+    fn pos(validators: &[&Keypair]) -> PoS {
+        let mut pos = PoS::new(SLOT_MS);
+        for validator in validators {
+            pos.add_participant(keys::address(&validator.public), 100);
+        }
+        pos
+    }
+
+    fn check(block: &Block, pos: &PoS) -> Result<(), ImportError> {
+        check_block(block, NOW).and_then(|_| check_block_context(block, &parent(), pos))
+    }
+
+    #[test]
+    fn valid_block_is_accepted() {
+        let validator = keys::test_keypair(1);
+        assert!(check(&child(&parent(), &validator, 1), &pos(&[&validator])).is_ok());
+    }
+
+    #[test]
+    fn block_errors_are_detected() {
+        let validator = keys::test_keypair(1);
+        let pos = pos(&[&validator]);
+        let block = child(&parent(), &validator, 1);
+
+        let mut bad_hash = block.clone();
+        bad_hash.header.nonce += 1;
+        assert!(matches!(check(&bad_hash, &pos), Err(ImportError::InvalidHash)));
+
+        let mut bad_root = block.clone();
+        bad_root.transactions.pop();
+        assert!(matches!(check(&bad_root, &pos), Err(ImportError::InvalidTransactionsRoot)));
+
+        let mut bad_signature = block.clone();
+        bad_signature.sign(&keys::test_keypair(2));
+        assert!(matches!(check(&bad_signature, &pos), Err(ImportError::InvalidSignature)));
+
+        let mut future = block.clone();
+        future.header.timestamp = NOW + MAX_FUTURE_DRIFT_MS + 1;
+        let future = seal(future, &validator);
+        assert!(matches!(check(&future, &pos), Err(ImportError::InvalidTimestamp(_))));
+    }
+
+    #[test]
+    fn transaction_errors_are_detected() {
+        let validator = keys::test_keypair(1);
+        let pos = pos(&[&validator]);
+
+        let mut forged = child(&parent(), &validator, 1);
+        forged.transactions[0].amount += 1;
+        forged.header.transactions_root = Block::calculate_transactions_root(&forged.transactions);
+        let forged = seal(forged, &validator);
+        assert!(matches!(check(&forged, &pos), Err(ImportError::InvalidTransaction { .. })));
+
+        // Награда не подписывается, но ее хеш должен соответствовать содержимому
+        let mut reward = child(&parent(), &validator, 1);
+        reward.transactions[1].amount = BLOCK_REWARD * 1000;
+        reward.header.transactions_root = Block::calculate_transactions_root(&reward.transactions);
+        let reward = seal(reward, &validator);
+        assert!(matches!(check(&reward, &pos), Err(ImportError::InvalidTransaction { hash, .. }) if hash == reward.transactions[1].hash));
+    }
+
+    #[test]
+    fn context_errors_are_detected() {
+        let validator = keys::test_keypair(1);
+        let pos = pos(&[&validator]);
+
+        let mut wrong_index = child(&parent(), &validator, 1);
+        wrong_index.header.index = 5;
+        let wrong_index = seal(wrong_index, &validator);
+        assert!(matches!(check(&wrong_index, &pos), Err(ImportError::InvalidIndex { expected: 1, got: 5 })));
+
+        let outsider = keys::test_keypair(2);
+        let foreign = child(&parent(), &outsider, 1);
+        assert!(matches!(check(&foreign, &pos), Err(ImportError::WrongLeader { .. })));
+
+        let mut wrong_slot = child(&parent(), &validator, 1);
+        wrong_slot.header.slot = 2;
+        let wrong_slot = seal(wrong_slot, &validator);
+        assert!(matches!(check(&wrong_slot, &pos), Err(ImportError::InvalidSlot)));
+    }
+}
@@ -1,42 +1,112 @@
 /*
     BufferMessage используется в качестве буфера сообщений, полученных от других узлов
-    Хранит сообщения 5 мин до его времени истечения, далее буфер автоматически очищается
+    Хранит хеш сообщения 5 мин с момента его получения узлом, далее буфер автоматически очищается.
+    Метка времени сообщения задается отправителем, поэтому для срока хранения не используется.
+    Сообщения с меткой времени вне допустимого окна отбрасываются: такое сообщение могло уже уйти из буфера,
+    и его повтор не был бы распознан.
 */
 use std::sync::Arc;
 use tokio::time::{sleep, Duration};
 use tokio::sync::Mutex;
-use std::collections::HashSet;
-use std::time::{SystemTime, UNIX_EPOCH};
-use crate::message::BufMessage;
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::message::Message;
+
+// Сколько хранится хеш полученного сообщения
+pub const SEEN_TTL: Duration = Duration::from_secs(5 * 60);
+// Допустимое расхождение часов узлов
+pub const MAX_CLOCK_DRIFT: Duration = Duration::from_secs(30);
+
+// Хеши сообщений, полученных или отправленных узлом за последние 5 минут, и время их получения
+pub type SeenMessages = Arc<Mutex<HashMap<String, Instant>>>;
+
+// Запоминает сообщение. false, если оно уже было получено ранее
+pub async fn mark_seen(seen: &SeenMessages, message: &Message) -> bool {
+    let mut seen = seen.lock().await;
+    if seen.contains_key(&message.hash) {
+        return false;
+    }
+    seen.insert(message.hash.clone(), Instant::now());
+    true
+}
+
+/*
+    true, если метка времени сообщения в допустимом окне относительно локального времени now (мс UNIX).
+    Сообщение из прошлого принимается, только пока его хеш гарантированно хранится в буфере.
+*/
+pub fn is_fresh(message: &Message, now: u128) -> bool {
+    let max_age = (SEEN_TTL - MAX_CLOCK_DRIFT).as_millis();
+    message.timestamp <= now + MAX_CLOCK_DRIFT.as_millis() && now.saturating_sub(message.timestamp) <= max_age
+}
+
+// Текущее время в миллисекундах UNIX
+pub fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis()
+}
+
+// Удаляет сообщения, полученные раньше, чем SEEN_TTL назад
+fn expire(seen: &mut HashMap<String, Instant>, now: Instant) {
+    seen.retain(|_, received| now.saturating_duration_since(*received) <= SEEN_TTL);
+}
 
 pub struct BufferMessage {
-    buffer: Arc<Mutex<HashSet<BufMessage>>>,
+    buffer: SeenMessages,
 }
 
 impl BufferMessage {
     // Создает новый буфер
-    pub fn new(buffer: Arc<Mutex<HashSet<BufMessage>>>) -> BufferMessage {
+    pub fn new(buffer: SeenMessages) -> BufferMessage {
         BufferMessage {
             buffer,
         }
     }
-    /* 
-        Функция, которая должна быть запущена в отдельном потоке. 
+    /*
+        Функция, которая должна быть запущена в отдельном потоке.
         Проверяет буффер каждые 5 секунд на наличие сообщений, у которых истек срок дейсвтия
         Удаляет сообщение, если оно находится в буфере больше, чем 5 минут
     */
     pub async fn start(&mut self) {
-        const FIVE_MINUTES_MILLIS: u128 = 5 * 60 * 1000; 
         loop {
             sleep(Duration::from_secs(5)).await;
-
-            let timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_millis();
-                
-            let mut buffer_guard = self.buffer.lock().await;
-            buffer_guard.retain(|msg| timestamp - msg.timestamp <= FIVE_MINUTES_MILLIS);
+            expire(&mut *self.buffer.lock().await, Instant::now());
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use crate::message::MessageType;
+
+    #[test]
+    fn timestamps_outside_window_are_rejected() {
+        let mut message = Message::new(MessageType::Transaction, Value::Null);
+        let now = message.timestamp;
+        assert!(is_fresh(&message, now));
+
+        message.timestamp = now + MAX_CLOCK_DRIFT.as_millis() + 1;
+        assert!(!is_fresh(&message, now));
+        message.timestamp = now - (SEEN_TTL - MAX_CLOCK_DRIFT).as_millis() - 1;
+        assert!(!is_fresh(&message, now));
+    }
+
+    #[tokio::test]
+    async fn expiry_uses_local_receive_time() {
+        let seen: SeenMessages = Arc::new(Mutex::new(HashMap::new()));
+        // Метка времени отправителя далеко в будущем не продлевает хранение
+        let mut message = Message::new(MessageType::Block, Value::Null);
+        message.timestamp += 24 * 60 * 60 * 1000;
+        assert!(mark_seen(&seen, &message).await);
+        assert!(!mark_seen(&seen, &message).await);
+
+        let received = seen.lock().await[&message.hash];
+        expire(&mut *seen.lock().await, received + SEEN_TTL);
+        assert!(!mark_seen(&seen, &message).await);
+        expire(&mut *seen.lock().await, received + SEEN_TTL + Duration::from_secs(1));
+        assert!(seen.lock().await.is_empty());
+    }
+}
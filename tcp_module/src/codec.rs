@@ -0,0 +1,231 @@
+/*
+    Кодек кадров для передачи сообщений по TCP.
+    TCP передает поток байтов, поэтому одно чтение может вернуть часть сообщения или несколько сообщений сразу.
+    Каждое сообщение передается в отдельном кадре:
+    [magic: 4 байта][версия: 1 байт][длина данных: u32 BE][контрольная сумма: 4 байта sha256 данных][данные]
+    FrameDecoder накапливает прочитанные байты и возвращает кадры по мере их полного получения.
+*/
+use std::fmt;
+use std::io;
+use sha2::{Sha256, Digest};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+// Признак начала кадра
+pub const FRAME_MAGIC: [u8; 4] = *b"OXN1";
+// Версия формата кадра
+pub const FRAME_VERSION: u8 = 1;
+// Размер заголовка кадра
+pub const FRAME_HEADER_SIZE: usize = 13;
+// Максимальный размер данных кадра по умолчанию
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    // Кадр начинается не с FRAME_MAGIC
+    BadMagic,
+    UnsupportedVersion(u8),
+    // Размер данных больше допустимого
+    TooLarge { size: usize, max: usize },
+    // Контрольная сумма не совпадает с данными
+    BadChecksum,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::BadMagic => write!(f, "Invalid frame magic"),
+            FrameError::UnsupportedVersion(version) => write!(f, "Unsupported frame version {}", version),
+            FrameError::TooLarge { size, max } => write!(f, "Frame of {} bytes exceeds the limit of {} bytes", size, max),
+            FrameError::BadChecksum => write!(f, "Invalid frame checksum"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let digest = Sha256::digest(payload);
+    let mut result = [0u8; 4];
+    result.copy_from_slice(&digest[..4]);
+    result
+}
+
+// Упаковывает данные в кадр
+pub fn encode_frame(payload: &[u8], max_frame_size: usize) -> Result<Vec<u8>, FrameError> {
+    if payload.len() > max_frame_size || payload.len() > u32::MAX as usize {
+        return Err(FrameError::TooLarge { size: payload.len(), max: max_frame_size });
+    }
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&FRAME_MAGIC);
+    frame.push(FRAME_VERSION);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&checksum(payload));
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+// Упаковывает данные в кадр и записывает его в соединение
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8], max_frame_size: usize) -> io::Result<()> {
+    let frame = encode_frame(payload, max_frame_size).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    writer.write_all(&frame).await
+}
+
+// Собирает кадры из байтов, прочитанных из соединения
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_size: usize) -> FrameDecoder {
+        FrameDecoder {
+            buffer: Vec::new(),
+            max_frame_size,
+        }
+    }
+
+    // Добавляет прочитанные байты
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /*
+        Возвращает данные следующего полностью полученного кадра, None - если кадр получен не полностью.
+        После ошибки поток нельзя продолжать читать, соединение следует закрыть.
+    */
+    pub fn decode(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+        if self.buffer[..4] != FRAME_MAGIC {
+            return Err(FrameError::BadMagic);
+        }
+        if self.buffer[4] != FRAME_VERSION {
+            return Err(FrameError::UnsupportedVersion(self.buffer[4]));
+        }
+
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&self.buffer[5..9]);
+        let length = u32::from_be_bytes(length) as usize;
+        // Размер проверяется до получения данных, чтобы не накапливать в памяти слишком большой кадр
+        if length > self.max_frame_size {
+            return Err(FrameError::TooLarge { size: length, max: self.max_frame_size });
+        }
+        if self.buffer.len() < FRAME_HEADER_SIZE + length {
+            return Ok(None);
+        }
+
+        let payload = self.buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + length].to_vec();
+        if self.buffer[9..13] != checksum(&payload) {
+            return Err(FrameError::BadChecksum);
+        }
+        self.buffer.drain(..FRAME_HEADER_SIZE + length);
+        Ok(Some(payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(decoder: &mut FrameDecoder) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.decode().unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn split_frame_is_reassembled() {
+        let payload = vec![7u8; 3000];
+        let frame = encode_frame(&payload, DEFAULT_MAX_FRAME_SIZE).unwrap();
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+
+        let mut frames = Vec::new();
+        for chunk in frame.chunks(5) {
+            decoder.extend(chunk);
+            frames.extend(decode_all(&mut decoder));
+        }
+        assert_eq!(frames, vec![payload]);
+    }
+
+    #[test]
+    fn coalesced_frames_are_separated() {
+        let mut bytes = encode_frame(b"first", DEFAULT_MAX_FRAME_SIZE).unwrap();
+        bytes.extend(encode_frame(b"", DEFAULT_MAX_FRAME_SIZE).unwrap());
+        bytes.extend(encode_frame(b"third", DEFAULT_MAX_FRAME_SIZE).unwrap());
+        // Часть следующего кадра в том же чтении
+        let fourth = encode_frame(b"fourth", DEFAULT_MAX_FRAME_SIZE).unwrap();
+        bytes.extend(&fourth[..7]);
+
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        decoder.extend(&bytes);
+        assert_eq!(decode_all(&mut decoder), vec![b"first".to_vec(), Vec::new(), b"third".to_vec()]);
+
+        decoder.extend(&fourth[7..]);
+        assert_eq!(decode_all(&mut decoder), vec![b"fourth".to_vec()]);
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        assert_eq!(encode_frame(&[0u8; 11], 10), Err(FrameError::TooLarge { size: 11, max: 10 }));
+
+        // Декодер отклоняет кадр по заголовку, не дожидаясь данных
+        let frame = encode_frame(&[0u8; 11], 100).unwrap();
+        let mut decoder = FrameDecoder::new(10);
+        decoder.extend(&frame[..FRAME_HEADER_SIZE]);
+        assert_eq!(decoder.decode(), Err(FrameError::TooLarge { size: 11, max: 10 }));
+    }
+
+    #[test]
+    fn corrupted_frames_are_rejected() {
+        let frame = encode_frame(b"payload", DEFAULT_MAX_FRAME_SIZE).unwrap();
+
+        let mut corrupted = frame.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        decoder.extend(&corrupted);
+        assert_eq!(decoder.decode(), Err(FrameError::BadChecksum));
+
+        let mut wrong_magic = frame.clone();
+        wrong_magic[0] = b'X';
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        decoder.extend(&wrong_magic);
+        assert_eq!(decoder.decode(), Err(FrameError::BadMagic));
+
+        let mut wrong_version = frame;
+        wrong_version[4] = 2;
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        decoder.extend(&wrong_version);
+        assert_eq!(decoder.decode(), Err(FrameError::UnsupportedVersion(2)));
+    }
+
+    // Случайные байты, в том числе с корректным заголовком кадра, должны давать ошибку или неполный кадр, а не панику
+    #[test]
+    fn random_bytes_never_panic() {
+        use rand::{Rng, SeedableRng};
+        use rand::rngs::StdRng;
+
+        let mut rng = StdRng::seed_from_u64(25);
+        for round in 0..5000 {
+            let mut bytes = if round % 2 == 0 {
+                Vec::new()
+            } else {
+                let payload: Vec<u8> = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect();
+                encode_frame(&payload, DEFAULT_MAX_FRAME_SIZE).unwrap()
+            };
+            bytes.extend((0..rng.gen_range(0..64)).map(|_| rng.gen::<u8>()));
+            if !bytes.is_empty() && round % 3 == 0 {
+                let position = rng.gen_range(0..bytes.len());
+                bytes[position] = rng.gen();
+            }
+
+            let mut decoder = FrameDecoder::new(1024);
+            decoder.extend(&bytes);
+            while let Ok(Some(frame)) = decoder.decode() {
+                let _ = crate::message::Message::decode(&frame);
+            }
+        }
+    }
+}
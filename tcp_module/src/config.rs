@@ -0,0 +1,37 @@
+/*
+    Настройки сетевого модуля, передаваемые из основного проекта.
+*/
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use ed25519_dalek::Keypair;
+use tokio::sync::watch;
+use crate::hello::ChainHead;
+use crate::reputation::Reputation;
+
+// Количество исходящих соединений по умолчанию
+pub const DEFAULT_TARGET_OUTBOUND: usize = 8;
+
+#[derive(Clone)]
+pub struct NetworkConfig {
+    // Ключ узла ed25519. Открытый ключ - идентификатор узла в сети, им подтверждается рукопожатие
+    pub identity: Arc<Keypair>,
+    // Максимальный размер данных одного кадра
+    pub max_frame_size: usize,
+    // Загрузочные узлы (host:port), с которых начинается поиск других узлов
+    pub bootstrap_peers: Vec<String>,
+    // Сколько исходящих соединений поддерживать. 0 - узел только принимает соединения
+    pub target_outbound: usize,
+    // Файл для сохранения адресов узлов, к которым удалось подключиться
+    pub peers_file: Option<PathBuf>,
+    // Идентификатор сети. Узлы с другим идентификатором отключаются при приветствии
+    pub chain_id: String,
+    // Хеш генезис-блока, должен совпадать у всех узлов сети
+    pub genesis_hash: String,
+    // Адрес для входящих соединений. Порт сообщается другим узлам в Hello
+    pub listen_address: SocketAddr,
+    // Последний блок основной цепочки, обновляется основным проектом
+    pub head: watch::Receiver<ChainHead>,
+    // Счета и блокировки узлов. Общие с основным проектом, который штрафует узлы за неверные блоки и транзакции
+    pub reputation: Reputation,
+}
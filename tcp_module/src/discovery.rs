@@ -0,0 +1,262 @@
+/*
+    Таблица известных адресов узлов.
+    Адреса берутся из списка загрузочных узлов, из файла с ранее работавшими адресами
+    и из ответов Peers других узлов. TCPConnect выбирает из таблицы адреса для новых исходящих подключений.
+    После неудачного подключения или разрыва соединения адрес откладывается с экспоненциальной задержкой.
+    Адреса, к которым удалось подключиться, сохраняются в файл и используются после перезапуска.
+*/
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use log::warn;
+
+// Максимальное количество адресов в таблице
+pub const MAX_KNOWN_PEERS: usize = 1000;
+// Максимальное количество адресов в одном сообщении Peers
+pub const MAX_PEERS_PER_MESSAGE: usize = 32;
+// После стольких неудачных подключений подряд адрес удаляется (кроме загрузочных узлов)
+const MAX_FAILURES: u32 = 10;
+// Начальная и максимальная задержка повторного подключения
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+// Соединение, прожившее дольше, считается стабильным, и счетчик неудач сбрасывается
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+
+pub type SharedPeerTable = Arc<Mutex<PeerTable>>;
+
+// Содержимое сообщения Peers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeersPayload {
+    pub addresses: Vec<String>,
+}
+
+struct PeerEntry {
+    // Количество неудачных подключений подряд
+    failures: u32,
+    // Раньше этого момента подключаться к адресу не нужно
+    next_attempt: Instant,
+    // К адресу хотя бы раз удалось подключиться
+    good: bool,
+    bootstrap: bool,
+}
+
+pub struct PeerTable {
+    entries: HashMap<String, PeerEntry>,
+    // Файл для сохранения рабочих адресов
+    path: Option<PathBuf>,
+}
+
+// Задержка перед следующей попыткой после failures неудач подряд
+pub fn backoff(failures: u32) -> Duration {
+    BASE_BACKOFF
+        .checked_mul(1u32 << failures.min(16))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF)
+}
+
+impl PeerTable {
+    // Создает таблицу из загрузочных узлов и адресов, сохраненных в path
+    pub fn new(path: Option<PathBuf>, bootstrap: &[String]) -> PeerTable {
+        let mut table = PeerTable {
+            entries: HashMap::new(),
+            path,
+        };
+        let now = Instant::now();
+
+        for address in table.load() {
+            table.entries.insert(address, PeerEntry { failures: 0, next_attempt: now, good: true, bootstrap: false });
+        }
+        for address in bootstrap {
+            table
+                .entries
+                .entry(address.clone())
+                .or_insert(PeerEntry { failures: 0, next_attempt: now, good: false, bootstrap: true })
+                .bootstrap = true;
+        }
+        table
+    }
+
+    fn load(&self) -> Vec<String> {
+        let path = match &self.path {
+            Some(path) if path.exists() => path,
+            _ => return Vec::new(),
+        };
+        match fs::read_to_string(path).map(|data| serde_json::from_str::<Vec<String>>(&data)) {
+            Ok(Ok(addresses)) => addresses,
+            Ok(Err(e)) => {
+                warn!("Failed to parse peers file {:?}: {}", path, e);
+                Vec::new()
+            }
+            Err(e) => {
+                warn!("Failed to read peers file {:?}: {}", path, e);
+                Vec::new()
+            }
+        }
+    }
+
+    // Сохраняет адреса, к которым удавалось подключиться
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut addresses: Vec<&String> = self.entries.iter().filter(|(_, entry)| entry.good).map(|(address, _)| address).collect();
+        addresses.sort();
+        let data = serde_json::to_string_pretty(&addresses).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // Запись через временный файл, чтобы при падении узла не остался обрезанный файл
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Добавляет адреса, полученные от другого узла. Принимаются только адреса вида ip:port
+    pub fn add_learned(&mut self, addresses: &[String], now: Instant) -> usize {
+        let mut added = 0;
+        for address in addresses.iter().take(MAX_PEERS_PER_MESSAGE) {
+            if self.entries.len() >= MAX_KNOWN_PEERS {
+                break;
+            }
+            if address.parse::<SocketAddr>().is_err() || self.entries.contains_key(address) {
+                continue;
+            }
+            self.entries.insert(address.clone(), PeerEntry { failures: 0, next_attempt: now, good: false, bootstrap: false });
+            added += 1;
+        }
+        added
+    }
+
+    // Адреса для подключения: не подключенные и не отложенные, сначала с меньшим количеством неудач
+    pub fn candidates(&self, now: Instant, exclude: &HashSet<String>, count: usize) -> Vec<String> {
+        let mut candidates: Vec<(&String, &PeerEntry)> = self
+            .entries
+            .iter()
+            .filter(|(address, entry)| entry.next_attempt <= now && !exclude.contains(*address))
+            .collect();
+        candidates.sort_by(|a, b| (a.1.failures, !a.1.good, a.0).cmp(&(b.1.failures, !b.1.good, b.0)));
+        candidates.into_iter().take(count).map(|(address, _)| address.clone()).collect()
+    }
+
+    // Адреса для ответа на GetPeers
+    pub fn sample(&self, count: usize) -> Vec<String> {
+        let mut addresses: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.good && entry.failures == 0)
+            .map(|(address, _)| address.clone())
+            .collect();
+        addresses.sort();
+        addresses.truncate(count);
+        addresses
+    }
+
+    pub fn mark_connected(&mut self, address: &str) {
+        let entry = self
+            .entries
+            .entry(address.to_string())
+            .or_insert(PeerEntry { failures: 0, next_attempt: Instant::now(), good: false, bootstrap: false });
+        entry.failures = 0;
+        let newly_good = !entry.good;
+        entry.good = true;
+        if newly_good {
+            if let Err(e) = self.save() {
+                warn!("Failed to save peers file: {}", e);
+            }
+        }
+    }
+
+    // Подключение не удалось: адрес откладывается, после MAX_FAILURES неудач удаляется
+    pub fn mark_failed(&mut self, address: &str, now: Instant) {
+        let remove = match self.entries.get_mut(address) {
+            Some(entry) => {
+                entry.failures += 1;
+                entry.next_attempt = now + backoff(entry.failures);
+                entry.failures > MAX_FAILURES && !entry.bootstrap
+            }
+            None => false,
+        };
+        if remove {
+            let was_good = self.entries.remove(address).map(|entry| entry.good).unwrap_or(false);
+            if was_good {
+                if let Err(e) = self.save() {
+                    warn!("Failed to save peers file: {}", e);
+                }
+            }
+        }
+    }
+
+    // Соединение разорвано. Короткое соединение считается неудачей, чтобы не переподключаться в цикле
+    pub fn mark_disconnected(&mut self, address: &str, now: Instant, lifetime: Duration) {
+        if let Some(entry) = self.entries.get_mut(address) {
+            if lifetime >= STABLE_CONNECTION {
+                entry.failures = 0;
+            }
+            entry.failures += 1;
+            entry.next_attempt = now + backoff(entry.failures);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_up_to_limit() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(20), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn failed_peers_are_postponed_and_dropped() {
+        let mut table = PeerTable::new(None, &[String::from("bootstrap:31313")]);
+        let now = Instant::now();
+        table.add_learned(&[String::from("10.0.0.1:31313"), String::from("not an address")], now);
+        assert_eq!(table.len(), 2);
+
+        table.mark_failed("10.0.0.1:31313", now);
+        let candidates = table.candidates(now, &HashSet::new(), 10);
+        assert_eq!(candidates, vec![String::from("bootstrap:31313")]);
+        assert_eq!(table.candidates(now + MAX_BACKOFF, &HashSet::new(), 10).len(), 2);
+
+        for _ in 0..MAX_FAILURES {
+            table.mark_failed("10.0.0.1:31313", now);
+            table.mark_failed("bootstrap:31313", now);
+        }
+        // Загрузочные узлы из таблицы не удаляются
+        assert_eq!(table.candidates(now + MAX_BACKOFF, &HashSet::new(), 10), vec![String::from("bootstrap:31313")]);
+    }
+
+    #[test]
+    fn good_peers_are_persisted() {
+        let path = std::env::temp_dir().join(format!("oxion_peers_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut table = PeerTable::new(Some(path.clone()), &[]);
+        table.add_learned(&[String::from("10.0.0.1:31313"), String::from("10.0.0.2:31313")], Instant::now());
+        table.mark_connected("10.0.0.2:31313");
+        assert_eq!(table.sample(10), vec![String::from("10.0.0.2:31313")]);
+
+        let restored = PeerTable::new(Some(path.clone()), &[]);
+        assert_eq!(restored.sample(10), vec![String::from("10.0.0.2:31313")]);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,23 @@
+/*
+    События сетевого модуля, передаваемые в основной проект.
+    Для каждого соединения создается отдельная очередь, через которую основной проект может отправить сообщение
+    именно этому узлу (например, ответ на запрос блоков).
+*/
+use std::net::SocketAddr;
+use tokio::sync::mpsc::Sender;
+use crate::hello::Hello;
+use crate::message::Message;
+
+// Размер очереди исходящих сообщений одного соединения
+pub const PEER_QUEUE_SIZE: usize = 32;
+
+#[derive(Debug)]
+pub enum NetworkEvent {
+    // Установлено новое соединение. node_id - подтвержденный ключ узла, hello - его приветствие,
+    // sender - очередь сообщений для этого узла
+    Connected { peer: SocketAddr, node_id: String, hello: Hello, sender: Sender<Message> },
+    // Получено сообщение от узла
+    Message { peer: SocketAddr, message: Message },
+    // Соединение закрыто
+    Disconnected { peer: SocketAddr },
+}
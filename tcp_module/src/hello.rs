@@ -0,0 +1,131 @@
+/*
+    Приветствие узлов (Hello).
+    Первое сообщение после установки защищенного канала. Пока стороны не обменялись Hello,
+    другие сообщения не принимаются. Узлы с несовместимой версией протокола, другой сетью (chain_id)
+    или другим генезис-блоком отключаются.
+*/
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+// Версия сетевого протокола данного узла
+pub const PROTOCOL_VERSION: u32 = 1;
+// Минимальная версия протокола, с которой узел может работать
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+// Возможности узла: синхронизация цепочки, пересылка транзакций и блоков, обмен адресами
+pub const CAPABILITIES: [&str; 3] = ["sync", "gossip", "peers"];
+
+// Последний блок основной цепочки
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainHead {
+    pub height: u64,
+    pub hash: String,
+    // Накопленный вес цепочки по правилу выбора основной цепочки
+    pub weight: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub chain_id: String,
+    pub genesis_hash: String,
+    pub best_height: u64,
+    pub best_hash: String,
+    // Вес цепочки узла. Более тяжелая цепочка может быть не длиннее нашей
+    #[serde(default)]
+    pub best_weight: u64,
+    // Ключ узла ed25519 в base64. Должен совпадать с ключом, подтвержденным при рукопожатии
+    pub node_id: String,
+    // Порт, на котором узел принимает входящие соединения
+    pub listen_port: u16,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HelloError {
+    // Первое сообщение не Hello или не разбирается
+    Malformed(String),
+    IncompatibleVersion(u32),
+    ChainIdMismatch(String),
+    GenesisMismatch(String),
+    // node_id не совпадает с ключом из рукопожатия
+    NodeIdMismatch,
+}
+
+impl fmt::Display for HelloError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HelloError::Malformed(reason) => write!(f, "Malformed hello: {}", reason),
+            HelloError::IncompatibleVersion(version) => write!(f, "Incompatible protocol version {}", version),
+            HelloError::ChainIdMismatch(chain_id) => write!(f, "Peer is on chain {}", chain_id),
+            HelloError::GenesisMismatch(hash) => write!(f, "Peer has genesis block {}", hash),
+            HelloError::NodeIdMismatch => write!(f, "Hello node id does not match the handshake key"),
+        }
+    }
+}
+
+impl std::error::Error for HelloError {}
+
+impl Hello {
+    // Проверяет приветствие удаленного узла. node_id - ключ, подтвержденный при рукопожатии
+    pub fn check(&self, remote: &Hello, node_id: &str) -> Result<(), HelloError> {
+        if remote.protocol_version < MIN_PROTOCOL_VERSION {
+            return Err(HelloError::IncompatibleVersion(remote.protocol_version));
+        }
+        if remote.chain_id != self.chain_id {
+            return Err(HelloError::ChainIdMismatch(remote.chain_id.clone()));
+        }
+        if remote.genesis_hash != self.genesis_hash {
+            return Err(HelloError::GenesisMismatch(remote.genesis_hash.clone()));
+        }
+        if remote.node_id != node_id {
+            return Err(HelloError::NodeIdMismatch);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(node_id: &str) -> Hello {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: String::from("oxion"),
+            genesis_hash: String::from("genesis"),
+            best_height: 0,
+            best_hash: String::from("genesis"),
+            best_weight: 0,
+            node_id: node_id.to_string(),
+            listen_port: 31313,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn matching_hello_is_accepted() {
+        let mut remote = hello("bob");
+        remote.best_height = 10;
+        remote.protocol_version = PROTOCOL_VERSION + 1;
+        assert_eq!(hello("alice").check(&remote, "bob"), Ok(()));
+    }
+
+    #[test]
+    fn mismatches_are_rejected() {
+        let local = hello("alice");
+
+        let mut remote = hello("bob");
+        remote.protocol_version = MIN_PROTOCOL_VERSION - 1;
+        assert_eq!(local.check(&remote, "bob"), Err(HelloError::IncompatibleVersion(0)));
+
+        let mut remote = hello("bob");
+        remote.chain_id = String::from("other");
+        assert_eq!(local.check(&remote, "bob"), Err(HelloError::ChainIdMismatch(String::from("other"))));
+
+        let mut remote = hello("bob");
+        remote.genesis_hash = String::from("other");
+        assert_eq!(local.check(&remote, "bob"), Err(HelloError::GenesisMismatch(String::from("other"))));
+
+        assert_eq!(local.check(&hello("bob"), "mallory"), Err(HelloError::NodeIdMismatch));
+    }
+}
@@ -1,6 +1,14 @@
 pub mod buffer;
+pub mod codec;
+pub mod config;
+pub mod discovery;
+pub mod event;
+pub mod hello;
 pub mod message;
 pub mod module;
+pub mod peer;
+pub mod reputation;
+pub mod secure;
 pub mod tcp_manager;
 pub mod tcp_stream;
 pub mod tcp_connect;
\ No newline at end of file
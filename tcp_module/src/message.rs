@@ -1,37 +1,78 @@
 /* 
     Содержит структуры для передачи сообщений между узлами сети.
+    Сообщения и их данные приходят от других узлов, поэтому разбираются только с проверкой:
+    ошибки разбора возвращаются как MessageError, за них узел штрафуется (reputation.rs).
 */
 
+use std::fmt;
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 use std::time::{SystemTime, UNIX_EPOCH};
 use sha2::{Sha256, Digest};
 use serde_json::Value;
 
 // Тип сообщения для общения узлов
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum MessageType {
     Transaction,
     Block,
+    // Состояние цепочки узла: генезис, высота и хеш последнего блока
     Status,
-    Connect,
+    // Приветствие узла (hello.rs). Первое сообщение после рукопожатия, позже не принимается
+    Hello,
+    // Запрос заголовков основной цепочки начиная с указанной высоты
+    GetHeaders,
+    Headers,
+    // Запрос блоков по хешам
+    GetBlocks,
+    Blocks,
+    // Запрос адресов известных узлов
+    GetPeers,
+    Peers,
 }
 
+// Сколько раз сообщение может быть переслано другими узлами
+pub const DEFAULT_TTL: u8 = 8;
+
 // Сообщение для общения узлов
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message {
     pub message_type: MessageType,
     pub timestamp: u128,
     pub data: Value,
     pub hash: String,
+    // Оставшееся количество пересылок. Уменьшается каждым узлом, поэтому не входит в хеш
+    #[serde(default = "default_ttl")]
+    pub ttl: u8,
 }
 
-// Сообщение для буфера, содержающее только уникальный хеш сообщения и время его создания 
-#[derive(Serialize, Deserialize, Debug, Eq, Hash, PartialEq)]
-pub struct BufMessage {
-    pub timestamp: u128,
-    pub hash: String,
+fn default_ttl() -> u8 {
+    DEFAULT_TTL
+}
+
+// Ошибки разбора сообщения другого узла
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageError {
+    // Данные не являются сообщением в формате JSON
+    Malformed(String),
+    // Хеш сообщения не совпадает с содержимым
+    InvalidHash,
+    // Данные сообщения не соответствуют его типу
+    InvalidPayload { message_type: MessageType, reason: String },
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::Malformed(reason) => write!(f, "Malformed message: {}", reason),
+            MessageError::InvalidHash => write!(f, "Message hash does not match its content"),
+            MessageError::InvalidPayload { message_type, reason } => write!(f, "Malformed {:?} payload: {}", message_type, reason),
+        }
+    }
 }
 
+impl std::error::Error for MessageError {}
+
 impl Message {
     // Создает новый объект сообщения
     pub fn new(message_type: MessageType, content: Value) -> Message {
@@ -40,15 +81,26 @@ impl Message {
         
         Message {
             message_type,
-            timestamp: timestamp,
+            timestamp,
             data: content,
-            hash: hash,
+            hash,
+            ttl: DEFAULT_TTL,
         }
     }
 
+    // true, если хеш соответствует содержимому сообщения
+    pub fn has_valid_hash(&self) -> bool {
+        Message::calculate_hash(&self.message_type, &self.data, self.timestamp) == self.hash
+    }
+
+    // Сообщения, которые распространяются по сети пересылкой (gossip)
+    pub fn is_gossip(&self) -> bool {
+        matches!(self.message_type, MessageType::Transaction | MessageType::Block)
+    }
+
     // Расчет хеша сообщения
     fn calculate_hash(message_type: &MessageType, data: &Value, timestamp: u128) -> String {
-        let data_str = serde_json::to_string(message_type).unwrap();
+        let data_str = serde_json::to_string(data).unwrap();
         let message_type_str = serde_json::to_string(message_type).unwrap();
         let input = format!("{}{}{}", message_type_str, data_str, timestamp);
         let mut hasher = Sha256::new();
@@ -71,17 +123,95 @@ impl Message {
     }
 
     /// Создание сообщения из строки JSON
-    pub fn from_json(json: &str) -> Self {
-        serde_json::from_str(json).unwrap()
+    pub fn from_json(json: &str) -> Result<Self, MessageError> {
+        Message::decode(json.as_bytes())
+    }
+
+    // Разбирает сообщение из расшифрованного кадра
+    pub fn decode(bytes: &[u8]) -> Result<Message, MessageError> {
+        serde_json::from_slice(bytes).map_err(|e| MessageError::Malformed(e.to_string()))
+    }
+
+    // Проверяет, что хеш соответствует содержимому
+    pub fn check_hash(&self) -> Result<(), MessageError> {
+        if self.has_valid_hash() {
+            Ok(())
+        } else {
+            Err(MessageError::InvalidHash)
+        }
+    }
+
+    // Разбирает данные сообщения в структуру, соответствующую его типу
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, MessageError> {
+        T::deserialize(&self.data).map_err(|e| MessageError::InvalidPayload {
+            message_type: self.message_type,
+            reason: e.to_string(),
+        })
     }
 }
 
-impl BufMessage {
-    // Создает новое сообщение для буфера из обычного сообщения
-    pub fn new(message: &Message) -> BufMessage {
-        BufMessage {
-            timestamp: message.timestamp,
-            hash: message.hash.clone(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn ttl_is_not_part_of_hash() {
+        let mut message = Message::new(MessageType::Transaction, json!({"amount": 10}));
+        assert_eq!(message.ttl, DEFAULT_TTL);
+        message.ttl -= 1;
+        assert!(message.has_valid_hash());
+
+        message.data = json!({"amount": 11});
+        assert!(!message.has_valid_hash());
+    }
+
+    // Случайные байты и искаженные сообщения должны давать ошибку, а не панику
+    #[test]
+    fn random_bytes_never_panic() {
+        use rand::{Rng, SeedableRng};
+        use rand::rngs::StdRng;
+
+        let mut rng = StdRng::seed_from_u64(25);
+        let valid = Message::new(MessageType::Peers, json!({"addresses": ["127.0.0.1:31313"]})).to_json().into_bytes();
+        for round in 0..5000 {
+            let bytes: Vec<u8> = if round % 2 == 0 {
+                let len = rng.gen_range(0..256);
+                (0..len).map(|_| rng.gen()).collect()
+            } else {
+                let mut bytes = valid.clone();
+                for _ in 0..rng.gen_range(1..4) {
+                    let position = rng.gen_range(0..bytes.len());
+                    bytes[position] = rng.gen();
+                }
+                bytes.truncate(rng.gen_range(1..=bytes.len()));
+                bytes
+            };
+            if let Ok(message) = Message::decode(&bytes) {
+                let _ = message.check_hash();
+                let _ = message.payload::<Vec<String>>();
+            }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn decoding_errors_are_typed() {
+        assert!(matches!(Message::from_json("{"), Err(MessageError::Malformed(_))));
+        let mut message = Message::new(MessageType::Peers, json!({"addresses": 5}));
+        assert_eq!(message.check_hash(), Ok(()));
+        assert!(matches!(
+            message.payload::<Vec<String>>(),
+            Err(MessageError::InvalidPayload { message_type: MessageType::Peers, .. })
+        ));
+        message.timestamp += 1;
+        assert_eq!(message.check_hash(), Err(MessageError::InvalidHash));
+    }
+
+    #[test]
+    fn missing_ttl_defaults() {
+        let message: Message = serde_json::from_str(
+            r#"{"message_type":"Block","timestamp":1,"data":null,"hash":"h"}"#,
+        ).unwrap();
+        assert_eq!(message.ttl, DEFAULT_TTL);
+    }
+}
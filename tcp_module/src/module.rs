@@ -4,47 +4,61 @@
 */
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use crate::tcp_stream::TCPStream;
 use crate::tcp_manager::TcpManager;
 use crate::message::Message;
 use tokio::sync::mpsc::{Receiver, Sender};
-use std::collections::HashMap;
-use tokio::sync::broadcast;
 use crate::buffer::BufferMessage;
 use crate::tcp_connect::TCPConnect;
+use crate::event::NetworkEvent;
+use crate::peer::{PeerContext, Peers};
+use crate::config::NetworkConfig;
+use crate::discovery::PeerTable;
 
 // Активирует модуль TCP соединений
 // Требует запуска в отдельном потоке
-pub async fn activate(receiver: Receiver<Message>) -> std::io::Result<()> {
+// События соединений и все сообщения от узлов передаются в events
+pub async fn activate(receiver: Receiver<Message>, events: Sender<NetworkEvent>, config: NetworkConfig) -> std::io::Result<()> {
     // // Создает буффер для разные потоков
-    let buffer_set = Arc::new(Mutex::new(HashSet::new()));
+    let buffer_set = Arc::new(Mutex::new(HashMap::new()));
     let buffer_set_clone = Arc::clone(&buffer_set);
 
     // // Запускает буффер на обновление данных каждые 5 минут
     let mut buffer_message = BufferMessage::new(buffer_set);
-    let buffer_stream = tokio::spawn(async move {
+    let _buffer_stream = tokio::spawn(async move {
         let _ = buffer_message.start().await;
     });
 
-    // let (tx, mut rx) = mpsc::channel(10);
-
-    let main_port = 31313;
-    
-    // Очередь для TCP Manager для клонирования сообщений на все подключенные узлы 
-    let (write_tx, _read_rx) = broadcast::channel::<String>(16);
+    // Список всех подключенных узлов, через него TCP Manager рассылает сообщения
+    let peers = Peers::new();
+    // Таблица известных адресов: загрузочные узлы и адреса, сохраненные при прошлом запуске
+    let table = Arc::new(Mutex::new(PeerTable::new(config.peers_file.clone(), &config.bootstrap_peers)));
+    let context = PeerContext {
+        identity: Arc::clone(&config.identity),
+        peers: peers.clone(),
+        seen: Arc::clone(&buffer_set_clone),
+        table,
+        events,
+        max_frame_size: config.max_frame_size,
+        chain_id: config.chain_id.clone(),
+        genesis_hash: config.genesis_hash.clone(),
+        listen_address: config.listen_address,
+        head: config.head.clone(),
+        reputation: config.reputation.clone(),
+    };
 
-    let mut tcp_manager = TcpManager::new(buffer_set_clone, receiver, write_tx.clone());
+    let mut tcp_manager = TcpManager::new(buffer_set_clone, receiver, peers);
     let _tcp_manager_stream = tokio::spawn(async move {
         let _ = tcp_manager.start_thread().await;
     });
     
-    let mut tcp_connect = TCPConnect::new();
-    let tcp_connect_stream = tokio::spawn(async move {
+    let tcp_connect = TCPConnect::new(context.clone(), config.target_outbound);
+    let _tcp_connect_stream = tokio::spawn(async move {
         let _ = tcp_connect.connect_peers().await;
     });
 
-    let tcp_stream = TCPStream::new(write_tx.clone());
+    let tcp_stream = TCPStream::new(context);
     let _ = tcp_stream.start_thread().await;
 
     Ok(())
@@ -0,0 +1,386 @@
+/*
+    Соединение с узлом сети.
+    Входящие (TCPStream) и исходящие (TCPConnect) соединения обслуживаются одинаково:
+    соединение регистрируется в общем списке узлов Peers, читает кадры и передает сообщения в основной проект,
+    записывает в сокет сообщения из своей очереди. Рассылка всем узлам идет через Peers.
+    Перед обменом сообщениями узлы выполняют рукопожатие (secure.rs), после него все кадры зашифрованы.
+    Первым зашифрованным сообщением узлы обмениваются приветствием Hello (hello.rs) и отключаются при несовпадении сети.
+    Скорость сообщений соединения ограничена, за нарушения узел штрафуется (reputation.rs) и при блокировке отключается.
+    Транзакции и блоки распространяются пересылкой: новое сообщение передается в основной проект, и после проверки
+    он рассылает его всем узлам через TCP Manager, пока не закончится ttl. Повторы отбрасываются по хешу.
+*/
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{mpsc, watch, Mutex};
+use log::{debug, error, info, warn};
+use crate::message::{Message, MessageType};
+use crate::event::{NetworkEvent, PEER_QUEUE_SIZE};
+use crate::codec::{write_frame, FrameDecoder};
+use crate::hello::{ChainHead, Hello, HelloError, CAPABILITIES, PROTOCOL_VERSION};
+use crate::secure::{self, SecureError, SecureSession, HANDSHAKE_TIMEOUT};
+use tokio::time::timeout;
+use ed25519_dalek::Keypair;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crate::buffer::{is_fresh, mark_seen, unix_millis, SeenMessages};
+use crate::discovery::{PeersPayload, SharedPeerTable, MAX_PEERS_PER_MESSAGE};
+use crate::reputation::{Misbehavior, RateLimiter, Reputation};
+
+// Кто инициировал соединение
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+pub struct PeerHandle {
+    pub direction: Direction,
+    // Ключ узла ed25519 в base64, подтвержденный при рукопожатии
+    pub node_id: String,
+    // Очередь сообщений для записи в соединение
+    pub sender: mpsc::Sender<Message>,
+}
+
+// Список подключенных узлов
+#[derive(Clone, Default)]
+pub struct Peers {
+    inner: Arc<Mutex<HashMap<SocketAddr, PeerHandle>>>,
+}
+
+impl Peers {
+    pub fn new() -> Peers {
+        Peers::default()
+    }
+
+    async fn add(&self, addr: SocketAddr, handle: PeerHandle) {
+        self.inner.lock().await.insert(addr, handle);
+    }
+
+    async fn remove(&self, addr: &SocketAddr) {
+        self.inner.lock().await.remove(addr);
+    }
+
+    // Отправляет сообщение одному узлу. false, если узел не подключен или его очередь переполнена
+    pub async fn send_to(&self, addr: &SocketAddr, message: Message) -> bool {
+        match self.inner.lock().await.get(addr) {
+            Some(peer) => peer.sender.try_send(message).is_ok(),
+            None => false,
+        }
+    }
+
+    // Отправляет сообщение всем узлам
+    pub async fn broadcast(&self, message: &Message) {
+        self.broadcast_except(message, None).await;
+    }
+
+    // Отправляет сообщение всем узлам, кроме except
+    pub async fn broadcast_except(&self, message: &Message, except: Option<SocketAddr>) {
+        let peers = self.inner.lock().await;
+        for (addr, peer) in peers.iter().filter(|(addr, _)| Some(**addr) != except) {
+            // Медленный узел не должен задерживать рассылку остальным
+            if peer.sender.try_send(message.clone()).is_err() {
+                warn!("Queue of peer {} is full, message dropped", addr);
+            }
+        }
+    }
+}
+
+// Общие параметры для всех соединений
+#[derive(Clone)]
+pub struct PeerContext {
+    // Ключ данного узла для рукопожатия
+    pub identity: Arc<Keypair>,
+    pub peers: Peers,
+    // Хеши уже полученных сообщений для отбрасывания повторов при пересылке
+    pub seen: SeenMessages,
+    // Известные адреса узлов
+    pub table: SharedPeerTable,
+    // Очередь событий для основного проекта
+    pub events: mpsc::Sender<NetworkEvent>,
+    // Максимальный размер данных одного кадра
+    pub max_frame_size: usize,
+    // Данные для приветствия Hello
+    pub chain_id: String,
+    pub genesis_hash: String,
+    pub listen_address: SocketAddr,
+    pub head: watch::Receiver<ChainHead>,
+    // Счета и блокировки узлов
+    pub reputation: Reputation,
+}
+
+impl PeerContext {
+    // Приветствие данного узла с текущей вершиной цепочки
+    pub fn hello(&self) -> Hello {
+        let head = self.head.borrow().clone();
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: self.chain_id.clone(),
+            genesis_hash: self.genesis_hash.clone(),
+            best_height: head.height,
+            best_hash: head.hash,
+            best_weight: head.weight,
+            node_id: BASE64.encode(self.identity.public.as_bytes()),
+            listen_port: self.listen_address.port(),
+            capabilities: CAPABILITIES.iter().map(|capability| capability.to_string()).collect(),
+        }
+    }
+}
+
+// Ошибка установки соединения
+#[derive(Debug)]
+pub enum EstablishError {
+    Secure(SecureError),
+    // Узел не прошел проверку приветствия
+    Hello(HelloError),
+    // Адрес или ключ узла заблокирован
+    Banned(String),
+}
+
+impl fmt::Display for EstablishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EstablishError::Secure(e) => write!(f, "{}", e),
+            EstablishError::Hello(e) => write!(f, "{}", e),
+            EstablishError::Banned(reason) => write!(f, "Peer is banned: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for EstablishError {}
+
+impl EstablishError {
+    /*
+        Нарушение протокола до завершения рукопожатия, за которое штрафуется адрес соединения.
+        Обрыв соединения, таймаут и несовместимость версии или цепочки нарушением не считаются.
+    */
+    pub fn misbehavior(&self) -> Option<Misbehavior> {
+        match self {
+            EstablishError::Secure(SecureError::Frame(_))
+            | EstablishError::Secure(SecureError::InvalidSignature)
+            | EstablishError::Secure(SecureError::Decrypt) => Some(Misbehavior::InvalidFrame),
+            EstablishError::Hello(HelloError::Malformed(_))
+            | EstablishError::Hello(HelloError::NodeIdMismatch) => Some(Misbehavior::MalformedMessage),
+            _ => None,
+        }
+    }
+}
+
+impl From<SecureError> for EstablishError {
+    fn from(e: SecureError) -> Self {
+        EstablishError::Secure(e)
+    }
+}
+
+impl From<HelloError> for EstablishError {
+    fn from(e: HelloError) -> Self {
+        EstablishError::Hello(e)
+    }
+}
+
+/*
+    Выполняет рукопожатие с узлом и обмен приветствиями. Возвращает сессию шифрования, приветствие узла
+    и декодер, в котором могут остаться байты, полученные сразу после приветствия.
+*/
+pub async fn establish(socket: &mut TcpStream, direction: Direction, context: &PeerContext) -> Result<(SecureSession, FrameDecoder, Hello), EstablishError> {
+    let mut decoder = FrameDecoder::new(context.max_frame_size);
+    let initiator = direction == Direction::Outbound;
+    let result = timeout(HANDSHAKE_TIMEOUT, async {
+        let mut session = secure::handshake(socket, &mut decoder, &context.identity, initiator, context.max_frame_size).await?;
+        if session.remote_id == BASE64.encode(context.identity.public.as_bytes()) {
+            return Err(SecureError::InvalidHandshake("connection to self".to_string()).into());
+        }
+        if let Some(ban) = context.reputation.banned(socket.peer_addr().map_err(SecureError::Io)?, Some(&session.remote_id)).await {
+            return Err(EstablishError::Banned(ban.reason));
+        }
+        let hello = exchange_hello(socket, &mut decoder, &mut session, context).await?;
+        Ok::<_, EstablishError>((session, hello))
+    })
+    .await
+    .map_err(|_| SecureError::InvalidHandshake("timed out".to_string()))??;
+
+    Ok((result.0, decoder, result.1))
+}
+
+// Отправляет приветствие и проверяет приветствие узла. Оно должно быть первым сообщением после рукопожатия
+async fn exchange_hello(socket: &mut TcpStream, decoder: &mut FrameDecoder, session: &mut SecureSession, context: &PeerContext) -> Result<Hello, EstablishError> {
+    let local = context.hello();
+    let data = serde_json::to_value(&local).map_err(|e| HelloError::Malformed(e.to_string()))?;
+    let ciphertext = session.encrypt(Message::new(MessageType::Hello, data).to_json().as_bytes())?;
+    write_frame(socket, &ciphertext, context.max_frame_size).await.map_err(SecureError::Io)?;
+
+    let frame = secure::read_frame(socket, decoder).await?;
+    let frame = session.decrypt(&frame)?;
+    let message = Message::decode(&frame).map_err(|e| HelloError::Malformed(e.to_string()))?;
+    if !matches!(message.message_type, MessageType::Hello) {
+        return Err(HelloError::Malformed(format!("expected Hello, received {:?}", message.message_type)).into());
+    }
+    let remote: Hello = message.payload().map_err(|e| HelloError::Malformed(e.to_string()))?;
+    local.check(&remote, &session.remote_id)?;
+    Ok(remote)
+}
+
+/*
+    Обслуживает соединение после рукопожатия до его закрытия.
+    Требует запуска в отдельной асинхронной задаче.
+*/
+pub async fn run_peer(
+    mut socket: TcpStream,
+    addr: SocketAddr,
+    direction: Direction,
+    context: PeerContext,
+    mut session: SecureSession,
+    mut decoder: FrameDecoder,
+    hello: Hello,
+) {
+    let node_id = session.remote_id.clone();
+    info!("Peer {:?} authenticated as {} at height {}", addr, node_id, hello.best_height);
+
+    // Входящее соединение приходит с временного порта, для подключения к узлу нужен порт из приветствия
+    if direction == Direction::Inbound && hello.listen_port != 0 {
+        let address = SocketAddr::new(addr.ip(), hello.listen_port).to_string();
+        context.table.lock().await.add_learned(&[address], Instant::now());
+    }
+
+    let (peer_tx, mut peer_rx) = mpsc::channel::<Message>(PEER_QUEUE_SIZE);
+    context.peers.add(addr, PeerHandle { direction, node_id: node_id.clone(), sender: peer_tx.clone() }).await;
+    // Сигнал о блокировке узла, после которого соединение закрывается
+    let kick = context.reputation.connect(addr, &node_id).await;
+    let mut limiter = RateLimiter::new(context.max_frame_size, Instant::now());
+    if context.events.send(NetworkEvent::Connected { peer: addr, node_id, hello, sender: peer_tx.clone() }).await.is_err() {
+        warn!("Network events receiver is closed");
+    }
+
+    // Исходящее соединение сразу запрашивает у узла известные ему адреса
+    if direction == Direction::Outbound {
+        let _ = peer_tx.try_send(Message::new(MessageType::GetPeers, Value::Null));
+    }
+
+    let mut buffer = [0; 4096];
+
+    loop {
+        tokio::select! {
+            result = socket.read(&mut buffer) => {
+                match result {
+                    Ok(0) => {
+                        break;
+                    }
+                    Ok(n) => {
+                        // Данные уже прочитаны и нужны для разбора потока, превышение только штрафуется
+                        if !limiter.allow_bytes(n, Instant::now()) {
+                            context.reputation.penalize(addr, Misbehavior::RateLimited).await;
+                        }
+                        decoder.extend(&buffer[..n]);
+                        // За одно чтение может прийти часть кадра или несколько кадров
+                        let frame = loop {
+                            let frame = match decoder.decode() {
+                                Ok(Some(frame)) => frame,
+                                Ok(None) => break Ok(()),
+                                Err(e) => break Err(SecureError::Frame(e)),
+                            };
+                            let frame = match session.decrypt(&frame) {
+                                Ok(frame) => frame,
+                                Err(e) => break Err(e),
+                            };
+                            debug!("Received from {:?}: {}", addr, String::from_utf8_lossy(&frame));
+
+                            match Message::decode(&frame) {
+                                Ok(message) => {
+                                    if !limiter.allow_message(&message.message_type, Instant::now()) {
+                                        debug!("Rate limit exceeded by {:?}, {:?} dropped", addr, message.message_type);
+                                        context.reputation.penalize(addr, Misbehavior::RateLimited).await;
+                                        continue;
+                                    }
+                                    if message.is_gossip() {
+                                        // Повторно полученные сообщения отбрасываются, иначе пересылка не закончится
+                                        if let Err(e) = message.check_hash() {
+                                            warn!("Message from {:?} rejected: {}", addr, e);
+                                            context.reputation.penalize(addr, Misbehavior::from(&e)).await;
+                                            continue;
+                                        }
+                                        if !is_fresh(&message, unix_millis()) {
+                                            debug!("Message {} from {:?} dropped: timestamp {} is out of range", message.hash, addr, message.timestamp);
+                                            continue;
+                                        }
+                                        // Пересылает основной проект после проверки транзакции или блока
+                                        if !mark_seen(&context.seen, &message).await {
+                                            continue;
+                                        }
+                                    }
+                                    match message.message_type {
+                                        MessageType::Hello => {
+                                            warn!("Repeated hello from {:?} ignored", addr);
+                                            context.reputation.penalize(addr, Misbehavior::UnexpectedHello).await;
+                                        },
+                                        MessageType::GetPeers => {
+                                            let addresses = context.table.lock().await.sample(MAX_PEERS_PER_MESSAGE);
+                                            match serde_json::to_value(PeersPayload { addresses }) {
+                                                Ok(data) => {
+                                                    let _ = peer_tx.try_send(Message::new(MessageType::Peers, data));
+                                                },
+                                                Err(e) => error!("Failed to serialize peers; error = {:?}", e),
+                                            }
+                                        },
+                                        MessageType::Peers => {
+                                            match message.payload::<PeersPayload>() {
+                                                Ok(payload) => {
+                                                    let added = context.table.lock().await.add_learned(&payload.addresses, Instant::now());
+                                                    debug!("Learned {} new peer addresses from {:?}", added, addr);
+                                                },
+                                                Err(e) => {
+                                                    warn!("Message from {:?} rejected: {}", addr, e);
+                                                    context.reputation.penalize(addr, Misbehavior::from(&e)).await;
+                                                },
+                                            }
+                                        },
+                                        _ => {
+                                            let _ = context.events.send(NetworkEvent::Message { peer: addr, message }).await;
+                                        },
+                                    }
+                                },
+                                Err(e) => {
+                                    warn!("Message from {:?} rejected: {}", addr, e);
+                                    context.reputation.penalize(addr, Misbehavior::from(&e)).await;
+                                }
+                            }
+                        };
+                        // Поток после ошибки кадра не восстановить, соединение закрывается
+                        if let Err(e) = frame {
+                            warn!("Invalid frame from {:?}: {}", addr, e);
+                            context.reputation.penalize(addr, Misbehavior::InvalidFrame).await;
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to read data from {:?}: {}", addr, e);
+                        break;
+                    }
+                }
+            }
+            Some(message) = peer_rx.recv() => {
+                let result = match session.encrypt(message.to_json().as_bytes()) {
+                    Ok(ciphertext) => write_frame(&mut socket, &ciphertext, context.max_frame_size).await,
+                    Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+                };
+                if let Err(e) = result {
+                    warn!("Failed to write data to {:?}: {}", addr, e);
+                    break;
+                }
+            }
+            _ = kick.notified() => {
+                warn!("Peer {:?} is banned, closing connection", addr);
+                break;
+            }
+        }
+    }
+
+    context.peers.remove(&addr).await;
+    context.reputation.disconnect(&addr).await;
+    let _ = context.events.send(NetworkEvent::Disconnected { peer: addr }).await;
+    info!("Connection with {:?} closed", addr);
+}
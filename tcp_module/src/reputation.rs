@@ -0,0 +1,606 @@
+/*
+    Репутация узлов.
+    У каждого узла (по ключу узла) есть счет, который уменьшается за некорректные сообщения, неверные блоки
+    и транзакции, превышение ограничений скорости. Штрафы со временем прощаются. Когда счет опускается
+    до порога, узел блокируется по ключу: соединения с ним закрываются, новые не принимаются.
+    Другие узлы на том же IP не затрагиваются.
+    Нарушения до завершения рукопожатия, когда ключ узла еще неизвестен, учитываются по IP для входящих соединений
+    и по адресу узла для исходящих.
+    Срок блокировки удваивается с каждой повторной, после нескольких блокировок она становится постоянной.
+    Истекшая блокировка забывается через неделю, после этого отсчет повторных блокировок начинается заново.
+    Блокировки сохраняются в файл и действуют после перезапуска.
+    Скорость сообщений ограничивается для каждого соединения отдельно (RateLimiter).
+*/
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, Notify};
+use log::{info, warn};
+use crate::message::{MessageError, MessageType};
+use crate::peer::Direction;
+
+// При таком счете узел блокируется
+pub const BAN_THRESHOLD: i32 = -100;
+// Сколько очков штрафа прощается за минуту
+const SCORE_RECOVERY_PER_MINUTE: i32 = 5;
+// Длительность первой временной блокировки, каждая следующая вдвое длиннее
+const BASE_BAN_DURATION: Duration = Duration::from_secs(3600);
+// После стольких временных блокировок узел блокируется навсегда
+const MAX_TEMPORARY_BANS: u32 = 3;
+// При таком счете IP блокируется за нарушения до рукопожатия
+pub const IP_BAN_THRESHOLD: i32 = -200;
+// Сколько помнить истекшую блокировку для удвоения срока следующей
+const BAN_MEMORY: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// Нарушения узла
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    // Сообщение не разбирается
+    MalformedMessage,
+    // Хеш сообщения не совпадает с содержимым
+    InvalidHash,
+    // Кадр поврежден или не расшифровывается
+    InvalidFrame,
+    // Повторное приветствие после установки соединения
+    UnexpectedHello,
+    // Превышено ограничение скорости
+    RateLimited,
+    // Транзакция с неверной подписью
+    InvalidTransaction,
+    // Блок не прошел проверку
+    InvalidBlock,
+    // Узел не ответил на запрос синхронизации или прислал меньше, чем обещал
+    IncompleteResponse,
+}
+
+impl Misbehavior {
+    pub fn penalty(self) -> i32 {
+        match self {
+            Misbehavior::MalformedMessage => 10,
+            Misbehavior::InvalidHash => 20,
+            Misbehavior::InvalidFrame => 50,
+            Misbehavior::UnexpectedHello => 10,
+            Misbehavior::RateLimited => 5,
+            Misbehavior::InvalidTransaction => 10,
+            Misbehavior::InvalidBlock => 25,
+            Misbehavior::IncompleteResponse => 10,
+        }
+    }
+}
+
+impl fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Misbehavior::MalformedMessage => write!(f, "malformed message"),
+            Misbehavior::InvalidHash => write!(f, "invalid message hash"),
+            Misbehavior::InvalidFrame => write!(f, "invalid frame"),
+            Misbehavior::UnexpectedHello => write!(f, "unexpected hello"),
+            Misbehavior::RateLimited => write!(f, "rate limit exceeded"),
+            Misbehavior::InvalidTransaction => write!(f, "invalid transaction"),
+            Misbehavior::InvalidBlock => write!(f, "invalid block"),
+            Misbehavior::IncompleteResponse => write!(f, "incomplete sync response"),
+        }
+    }
+}
+
+impl From<&MessageError> for Misbehavior {
+    fn from(e: &MessageError) -> Self {
+        match e {
+            MessageError::Malformed(_) | MessageError::InvalidPayload { .. } => Misbehavior::MalformedMessage,
+            MessageError::InvalidHash => Misbehavior::InvalidHash,
+        }
+    }
+}
+
+// Кого блокировать: весь IP, адрес соединения (нарушения до рукопожатия) или ключ узла
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanTarget {
+    Ip(IpAddr),
+    Address(SocketAddr),
+    Node(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub target: BanTarget,
+    // Время окончания блокировки (секунды UNIX). None - постоянная блокировка
+    pub until: Option<u64>,
+    pub reason: String,
+    // Сколько раз цель уже блокировалась
+    pub count: u32,
+}
+
+impl Ban {
+    pub fn is_active(&self, now: u64) -> bool {
+        self.until.map(|until| until > now).unwrap_or(true)
+    }
+}
+
+// Счет подключенного узла для ответа RPC
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerScore {
+    pub address: SocketAddr,
+    pub node_id: String,
+    pub score: i32,
+}
+
+struct Score {
+    value: i32,
+    // Время последнего пересчета (секунды UNIX)
+    updated: u64,
+}
+
+impl Score {
+    fn new(now: u64) -> Score {
+        Score { value: 0, updated: now }
+    }
+
+    // Текущий счет с учетом прощенных штрафов
+    fn current(&mut self, now: u64) -> i32 {
+        let minutes = now.saturating_sub(self.updated) / 60;
+        if minutes > 0 {
+            let recovered = (minutes.min(i32::MAX as u64) as i32).saturating_mul(SCORE_RECOVERY_PER_MINUTE);
+            self.value = self.value.saturating_add(recovered).min(0);
+            self.updated += minutes * 60;
+        }
+        self.value
+    }
+}
+
+struct Connection {
+    node_id: String,
+    // Сигнал соединению закрыться
+    kick: Arc<Notify>,
+}
+
+pub struct ReputationTable {
+    scores: HashMap<String, Score>,
+    // Счета IP и адресов, нарушивших протокол до завершения рукопожатия
+    handshake_scores: HashMap<BanTarget, Score>,
+    bans: HashMap<BanTarget, Ban>,
+    connections: HashMap<SocketAddr, Connection>,
+    // Файл для сохранения блокировок
+    path: Option<PathBuf>,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+impl ReputationTable {
+    pub fn new(path: Option<PathBuf>) -> ReputationTable {
+        let mut table = ReputationTable {
+            scores: HashMap::new(),
+            handshake_scores: HashMap::new(),
+            bans: HashMap::new(),
+            connections: HashMap::new(),
+            path,
+        };
+        for ban in table.load() {
+            table.bans.insert(ban.target.clone(), ban);
+        }
+        table
+    }
+
+    fn load(&self) -> Vec<Ban> {
+        let path = match &self.path {
+            Some(path) if path.exists() => path,
+            _ => return Vec::new(),
+        };
+        match fs::read_to_string(path).map(|data| serde_json::from_str::<Vec<Ban>>(&data)) {
+            Ok(Ok(bans)) => bans,
+            Ok(Err(e)) => {
+                warn!("Failed to parse bans file {:?}: {}", path, e);
+                Vec::new()
+            }
+            Err(e) => {
+                warn!("Failed to read bans file {:?}: {}", path, e);
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let bans: Vec<&Ban> = self.bans.values().collect();
+        let data = serde_json::to_string_pretty(&bans).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)
+    }
+
+    // Действующая блокировка IP, адреса соединения или ключа узла
+    pub fn banned(&self, addr: SocketAddr, node_id: Option<&str>, now: u64) -> Option<&Ban> {
+        let active = |target: BanTarget| self.bans.get(&target).filter(|ban| ban.is_active(now));
+        active(BanTarget::Ip(addr.ip()))
+            .or_else(|| active(BanTarget::Address(addr)))
+            .or_else(|| node_id.and_then(|node_id| active(BanTarget::Node(node_id.to_string()))))
+    }
+
+    // Текущий счет узла с учетом прощенных штрафов
+    pub fn score(&mut self, node_id: &str, now: u64) -> i32 {
+        self.scores.entry(node_id.to_string()).or_insert(Score::new(now)).current(now)
+    }
+
+    /*
+        Штрафует узел. Если счет опустился до порога, блокирует его ключ
+        и возвращает соединения этого узла, которые нужно закрыть.
+    */
+    pub fn penalize(&mut self, node_id: &str, misbehavior: Misbehavior, now: u64) -> Vec<Arc<Notify>> {
+        let value = self.score(node_id, now) - misbehavior.penalty();
+        if let Some(score) = self.scores.get_mut(node_id) {
+            score.value = value;
+        }
+        if value > BAN_THRESHOLD {
+            return Vec::new();
+        }
+
+        self.ban(BanTarget::Node(node_id.to_string()), &misbehavior.to_string(), now);
+        self.scores.remove(node_id);
+        self.save_bans();
+
+        self.connections
+            .values()
+            .filter(|connection| connection.node_id == node_id)
+            .map(|connection| Arc::clone(&connection.kick))
+            .collect()
+    }
+
+    /*
+        Штрафует соединение, нарушившее протокол до завершения рукопожатия, когда ключ узла еще неизвестен.
+        Входящие соединения каждый раз приходят с нового порта, поэтому учитываются по IP с более низким порогом:
+        за одним IP может быть несколько честных узлов. Исходящие - по адресу, к которому подключался узел.
+    */
+    pub fn penalize_handshake(&mut self, addr: SocketAddr, direction: Direction, misbehavior: Misbehavior, now: u64) {
+        let (target, threshold) = match direction {
+            Direction::Inbound => (BanTarget::Ip(addr.ip()), IP_BAN_THRESHOLD),
+            Direction::Outbound => (BanTarget::Address(addr), BAN_THRESHOLD),
+        };
+        // Счета, полностью восстановившиеся, больше не нужны
+        self.handshake_scores.retain(|_, score| score.current(now) < 0);
+        let score = self.handshake_scores.entry(target.clone()).or_insert(Score::new(now));
+        score.value -= misbehavior.penalty();
+        if score.value > threshold {
+            return;
+        }
+
+        self.handshake_scores.remove(&target);
+        self.ban(target, &misbehavior.to_string(), now);
+        self.save_bans();
+    }
+
+    fn save_bans(&self) {
+        if let Err(e) = self.save() {
+            warn!("Failed to save bans file: {}", e);
+        }
+    }
+
+    fn ban(&mut self, target: BanTarget, reason: &str, now: u64) {
+        // Давно истекшие временные блокировки забываются, иначе таблица и файл растут без ограничений
+        self.bans.retain(|_, ban| ban.until.map(|until| until + BAN_MEMORY.as_secs() > now).unwrap_or(true));
+        let count = self.bans.get(&target).map(|ban| ban.count).unwrap_or(0) + 1;
+        let until = if count > MAX_TEMPORARY_BANS {
+            None
+        } else {
+            Some(now + BASE_BAN_DURATION.as_secs() * (1u64 << (count - 1)))
+        };
+        info!("Banned {:?} until {:?}: {}", target, until, reason);
+        self.bans.insert(target.clone(), Ban { target, until, reason: reason.to_string(), count });
+    }
+
+    // Действующие блокировки
+    pub fn bans(&self, now: u64) -> Vec<Ban> {
+        let mut bans: Vec<Ban> = self.bans.values().filter(|ban| ban.is_active(now)).cloned().collect();
+        bans.sort_by_key(|ban| ban.until);
+        bans
+    }
+
+    // Счета подключенных узлов
+    pub fn peer_scores(&mut self, now: u64) -> Vec<PeerScore> {
+        let connections: Vec<(SocketAddr, String)> = self
+            .connections
+            .iter()
+            .map(|(addr, connection)| (*addr, connection.node_id.clone()))
+            .collect();
+        let mut scores: Vec<PeerScore> = connections
+            .into_iter()
+            .map(|(address, node_id)| {
+                let score = self.score(&node_id, now);
+                PeerScore { address, node_id, score }
+            })
+            .collect();
+        scores.sort_by_key(|peer| peer.address);
+        scores
+    }
+}
+
+// Общая таблица репутации для сетевого модуля и основного проекта
+#[derive(Clone)]
+pub struct Reputation {
+    inner: Arc<Mutex<ReputationTable>>,
+}
+
+impl Reputation {
+    pub fn new(path: Option<PathBuf>) -> Reputation {
+        Reputation { inner: Arc::new(Mutex::new(ReputationTable::new(path))) }
+    }
+
+    pub async fn banned(&self, addr: SocketAddr, node_id: Option<&str>) -> Option<Ban> {
+        self.inner.lock().await.banned(addr, node_id, unix_now()).cloned()
+    }
+
+    // Регистрирует соединение. Через возвращаемый сигнал соединению сообщается о блокировке узла
+    pub async fn connect(&self, addr: SocketAddr, node_id: &str) -> Arc<Notify> {
+        let kick = Arc::new(Notify::new());
+        self.inner
+            .lock()
+            .await
+            .connections
+            .insert(addr, Connection { node_id: node_id.to_string(), kick: Arc::clone(&kick) });
+        kick
+    }
+
+    pub async fn disconnect(&self, addr: &SocketAddr) {
+        self.inner.lock().await.connections.remove(addr);
+    }
+
+    // Штрафует узел подключенного соединения addr
+    pub async fn penalize(&self, addr: SocketAddr, misbehavior: Misbehavior) {
+        let mut table = self.inner.lock().await;
+        let node_id = match table.connections.get(&addr) {
+            Some(connection) => connection.node_id.clone(),
+            None => return,
+        };
+        warn!("Peer {} penalized: {}", addr, misbehavior);
+        for kick in table.penalize(&node_id, misbehavior, unix_now()) {
+            kick.notify_one();
+        }
+    }
+
+    // Штрафует соединение addr, которое еще не прошло рукопожатие
+    pub async fn penalize_handshake(&self, addr: SocketAddr, direction: Direction, misbehavior: Misbehavior) {
+        warn!("Connection {} penalized before handshake: {}", addr, misbehavior);
+        self.inner.lock().await.penalize_handshake(addr, direction, misbehavior, unix_now());
+    }
+
+    pub async fn bans(&self) -> Vec<Ban> {
+        self.inner.lock().await.bans(unix_now())
+    }
+
+    pub async fn peer_scores(&self) -> Vec<PeerScore> {
+        self.inner.lock().await.peer_scores(unix_now())
+    }
+}
+
+// Ограничение скорости: корзина на capacity жетонов, пополняемая со скоростью rate в секунду
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, rate: f64, now: Instant) -> TokenBucket {
+        TokenBucket { capacity, tokens: capacity, rate, updated: now }
+    }
+
+    fn take(&mut self, cost: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+        if self.tokens < cost {
+            return false;
+        }
+        self.tokens -= cost;
+        true
+    }
+}
+
+// Группы сообщений с общим ограничением скорости
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RateClass {
+    Transaction,
+    Block,
+    // Запросы, на которые узел отвечает данными: GetHeaders, GetBlocks, GetPeers
+    Request,
+    Other,
+}
+
+impl RateClass {
+    fn of(message_type: &MessageType) -> RateClass {
+        match message_type {
+            MessageType::Transaction => RateClass::Transaction,
+            MessageType::Block => RateClass::Block,
+            MessageType::GetHeaders | MessageType::GetBlocks | MessageType::GetPeers => RateClass::Request,
+            _ => RateClass::Other,
+        }
+    }
+
+    // Размер корзины и скорость пополнения в сообщениях в секунду
+    fn limits(self) -> (f64, f64) {
+        match self {
+            RateClass::Transaction => (200.0, 100.0),
+            RateClass::Block => (32.0, 8.0),
+            RateClass::Request => (20.0, 5.0),
+            RateClass::Other => (64.0, 16.0),
+        }
+    }
+}
+
+// Байт в секунду, которые может прислать одно соединение
+const BYTES_PER_SECOND: f64 = 1024.0 * 1024.0;
+
+// Ограничения скорости одного соединения: по количеству сообщений каждой группы и по объему данных
+pub struct RateLimiter {
+    messages: HashMap<RateClass, TokenBucket>,
+    bytes: TokenBucket,
+}
+
+impl RateLimiter {
+    // Корзина байтов вмещает два кадра максимального размера, чтобы крупные ответы Blocks проходили
+    pub fn new(max_frame_size: usize, now: Instant) -> RateLimiter {
+        let capacity = (2 * max_frame_size) as f64;
+        RateLimiter {
+            messages: HashMap::new(),
+            bytes: TokenBucket::new(capacity.max(BYTES_PER_SECOND), BYTES_PER_SECOND, now),
+        }
+    }
+
+    // false, если соединение прислало больше данных, чем разрешено
+    pub fn allow_bytes(&mut self, bytes: usize, now: Instant) -> bool {
+        self.bytes.take(bytes as f64, now)
+    }
+
+    // false, если сообщений этого типа слишком много
+    pub fn allow_message(&mut self, message_type: &MessageType, now: Instant) -> bool {
+        let class = RateClass::of(message_type);
+        let (capacity, rate) = class.limits();
+        self.messages
+            .entry(class)
+            .or_insert_with(|| TokenBucket::new(capacity, rate, now))
+            .take(1.0, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8, port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, last], port))
+    }
+
+    #[test]
+    fn score_drops_to_ban_and_recovers() {
+        let mut table = ReputationTable::new(None);
+        table.penalize("alice", Misbehavior::InvalidBlock, 0);
+        assert_eq!(table.score("alice", 0), -25);
+        // Через 5 минут прощено 25 очков
+        assert_eq!(table.score("alice", 300), 0);
+
+        for _ in 0..4 {
+            table.penalize("alice", Misbehavior::InvalidBlock, 300);
+        }
+        assert!(table.banned(addr(2, 9000), Some("alice"), 300).is_some());
+        // Другие узлы на том же IP не блокируются
+        assert!(table.banned(addr(1, 9000), None, 300).is_none());
+        assert!(table.banned(addr(1, 9001), Some("bob"), 300).is_none());
+        assert!(table.banned(addr(1, 9000), Some("alice"), 300 + BASE_BAN_DURATION.as_secs()).is_none());
+    }
+
+    #[test]
+    fn repeated_bans_become_permanent() {
+        let mut table = ReputationTable::new(None);
+        let mut now = 0;
+        for count in 1..=MAX_TEMPORARY_BANS + 1 {
+            for _ in 0..2 {
+                table.penalize("alice", Misbehavior::InvalidFrame, now);
+            }
+            let ban = table.banned(addr(1, 9000), Some("alice"), now).cloned().expect("peer should be banned");
+            assert_eq!(ban.count, count);
+            if count > MAX_TEMPORARY_BANS {
+                assert_eq!(ban.until, None);
+            } else {
+                assert_eq!(ban.until, Some(now + (BASE_BAN_DURATION.as_secs() << (count - 1))));
+                now = ban.until.unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn inbound_handshake_abuse_bans_ip() {
+        let mut table = ReputationTable::new(None);
+        // Каждое соединение приходит с нового порта, но счет ведется по IP
+        let failures = (-IP_BAN_THRESHOLD / Misbehavior::InvalidFrame.penalty()) as u16;
+        for port in 1..failures {
+            table.penalize_handshake(addr(1, 40000 + port), Direction::Inbound, Misbehavior::InvalidFrame, 0);
+            assert!(table.banned(addr(1, 9000), None, 0).is_none());
+        }
+        table.penalize_handshake(addr(1, 40000 + failures), Direction::Inbound, Misbehavior::InvalidFrame, 0);
+        let bans = table.bans(0);
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].target, BanTarget::Ip(addr(1, 0).ip()));
+        assert!(table.banned(addr(1, 9000), None, 0).is_some());
+        assert!(table.banned(addr(2, 9000), None, 0).is_none());
+
+        // Исходящее подключение к узлу с неверным рукопожатием блокирует только его адрес
+        for _ in 0..2 {
+            table.penalize_handshake(addr(3, 9000), Direction::Outbound, Misbehavior::InvalidFrame, 0);
+        }
+        assert!(table.banned(addr(3, 9000), None, 0).is_some());
+        assert!(table.banned(addr(3, 9001), None, 0).is_none());
+    }
+
+    #[test]
+    fn expired_bans_are_forgotten() {
+        let mut table = ReputationTable::new(None);
+        for _ in 0..2 {
+            table.penalize("alice", Misbehavior::InvalidFrame, 0);
+        }
+        let until = table.bans[&BanTarget::Node(String::from("alice"))].until.unwrap();
+
+        // Повторная блокировка в пределах BAN_MEMORY учитывает предыдущую
+        let now = until + 1;
+        for _ in 0..2 {
+            table.penalize("bob", Misbehavior::InvalidFrame, now);
+        }
+        assert!(table.bans.contains_key(&BanTarget::Node(String::from("alice"))));
+
+        let later = until + BAN_MEMORY.as_secs();
+        for _ in 0..2 {
+            table.penalize("carol", Misbehavior::InvalidFrame, later);
+        }
+        assert!(!table.bans.contains_key(&BanTarget::Node(String::from("alice"))));
+        assert_eq!(table.bans.len(), 2);
+    }
+
+    #[test]
+    fn bans_are_persisted() {
+        let path = std::env::temp_dir().join(format!("oxion_bans_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut table = ReputationTable::new(Some(path.clone()));
+        let now = unix_now();
+        table.penalize("alice", Misbehavior::InvalidFrame, now);
+        table.penalize("alice", Misbehavior::InvalidFrame, now);
+        table.penalize_handshake(addr(2, 9000), Direction::Outbound, Misbehavior::InvalidFrame, now);
+        table.penalize_handshake(addr(2, 9000), Direction::Outbound, Misbehavior::InvalidFrame, now);
+
+        let restored = ReputationTable::new(Some(path.clone()));
+        assert_eq!(restored.bans(now).len(), 2);
+        assert!(restored.banned(addr(3, 9000), Some("alice"), now).is_some());
+        assert!(restored.banned(addr(2, 9000), None, now).is_some());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn token_bucket_limits_rate() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(1024, now);
+        let (capacity, rate) = RateClass::Request.limits();
+        for _ in 0..capacity as usize {
+            assert!(limiter.allow_message(&MessageType::GetBlocks, now));
+        }
+        assert!(!limiter.allow_message(&MessageType::GetHeaders, now));
+        // Другие группы ограничиваются отдельно
+        assert!(limiter.allow_message(&MessageType::Transaction, now));
+        assert!(limiter.allow_message(&MessageType::GetPeers, now + Duration::from_secs_f64(1.0 / rate)));
+
+        assert!(limiter.allow_bytes(BYTES_PER_SECOND as usize, now));
+        assert!(!limiter.allow_bytes(1, now));
+    }
+}
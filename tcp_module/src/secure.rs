@@ -0,0 +1,345 @@
+/*
+    Защищенный канал между узлами.
+    Рукопожатие:
+    1. Стороны обмениваются временными открытыми ключами X25519 в открытом кадре.
+    2. Из общего секрета через HKDF-SHA256 выводятся два ключа ChaCha20-Poly1305, по одному на каждое направление.
+    3. Каждая сторона отправляет зашифрованный кадр с открытым ключом узла ed25519 (идентификатор узла)
+       и подписью над своей ролью (инициатор или отвечающий) и обоими временными ключами. Подпись доказывает владение
+       ключом узла и привязывает его к сессии, поэтому посредник не может подменить временные ключи.
+       Роль в подписи не дает выдать подпись одной стороны, отраженную обратно, за подпись другой.
+    После рукопожатия данные каждого кадра шифруются. Nonce - номер кадра в направлении,
+    поэтому повтор, потеря или перестановка кадров приводят к ошибке расшифровки.
+*/
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::Aead;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey};
+use crate::codec::{write_frame, FrameDecoder, FrameError};
+
+const HANDSHAKE_DOMAIN: &[u8] = b"oxion.handshake.v1";
+const INITIATOR_KEY_INFO: &[u8] = b"oxion.session.initiator";
+const RESPONDER_KEY_INFO: &[u8] = b"oxion.session.responder";
+// Время на рукопожатие, после которого соединение закрывается
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum SecureError {
+    Io(io::Error),
+    Frame(FrameError),
+    // Соединение закрыто до завершения рукопожатия
+    Closed,
+    InvalidHandshake(String),
+    // Подпись рукопожатия не соответствует ключу узла
+    InvalidSignature,
+    // Кадр не расшифровывается: данные изменены, повторены или переставлены
+    Decrypt,
+}
+
+impl fmt::Display for SecureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecureError::Io(e) => write!(f, "I/O error: {}", e),
+            SecureError::Frame(e) => write!(f, "{}", e),
+            SecureError::Closed => write!(f, "Connection closed during handshake"),
+            SecureError::InvalidHandshake(reason) => write!(f, "Invalid handshake: {}", reason),
+            SecureError::InvalidSignature => write!(f, "Invalid handshake signature"),
+            SecureError::Decrypt => write!(f, "Failed to decrypt frame"),
+        }
+    }
+}
+
+impl std::error::Error for SecureError {}
+
+impl From<io::Error> for SecureError {
+    fn from(e: io::Error) -> Self {
+        SecureError::Io(e)
+    }
+}
+
+impl From<FrameError> for SecureError {
+    fn from(e: FrameError) -> Self {
+        SecureError::Frame(e)
+    }
+}
+
+// Подтверждение владения ключом узла
+#[derive(Serialize, Deserialize)]
+struct AuthPayload {
+    // Открытый ключ узла ed25519 в base64
+    node_id: String,
+    // Подпись над временными ключами сессии в base64
+    signature: String,
+}
+
+pub struct SecureSession {
+    send: ChaCha20Poly1305,
+    receive: ChaCha20Poly1305,
+    send_counter: u64,
+    receive_counter: u64,
+    // Идентификатор удаленного узла: его открытый ключ ed25519 в base64
+    pub remote_id: String,
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut bytes = [0u8; 12];
+    bytes[4..].copy_from_slice(&counter.to_be_bytes());
+    Nonce::from(bytes)
+}
+
+// Байты, которые подписывает сторона: ее роль и временные ключи инициатора и отвечающей стороны
+fn transcript(signer_is_initiator: bool, initiator: &[u8; 32], responder: &[u8; 32]) -> Vec<u8> {
+    let role: &[u8] = if signer_is_initiator { b"initiator" } else { b"responder" };
+    let mut bytes = Vec::with_capacity(HANDSHAKE_DOMAIN.len() + role.len() + 64);
+    bytes.extend_from_slice(HANDSHAKE_DOMAIN);
+    bytes.extend_from_slice(role);
+    bytes.extend_from_slice(initiator);
+    bytes.extend_from_slice(responder);
+    bytes
+}
+
+// Ключи шифрования для направлений инициатор -> отвечающий и отвечающий -> инициатор
+fn derive_keys(shared: &[u8; 32], initiator: &[u8; 32], responder: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut salt = Vec::with_capacity(64);
+    salt.extend_from_slice(initiator);
+    salt.extend_from_slice(responder);
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared);
+
+    let mut initiator_key = [0u8; 32];
+    let mut responder_key = [0u8; 32];
+    hkdf.expand(INITIATOR_KEY_INFO, &mut initiator_key).expect("32 bytes is a valid HKDF output length");
+    hkdf.expand(RESPONDER_KEY_INFO, &mut responder_key).expect("32 bytes is a valid HKDF output length");
+    (initiator_key, responder_key)
+}
+
+impl SecureSession {
+    fn new(send_key: [u8; 32], receive_key: [u8; 32]) -> SecureSession {
+        SecureSession {
+            send: ChaCha20Poly1305::new(Key::from_slice(&send_key)),
+            receive: ChaCha20Poly1305::new(Key::from_slice(&receive_key)),
+            send_counter: 0,
+            receive_counter: 0,
+            remote_id: String::new(),
+        }
+    }
+
+    // Шифрует данные следующего исходящего кадра
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, SecureError> {
+        let ciphertext = self.send.encrypt(&nonce(self.send_counter), plaintext).map_err(|_| SecureError::Decrypt)?;
+        self.send_counter += 1;
+        Ok(ciphertext)
+    }
+
+    // Расшифровывает данные следующего входящего кадра
+    pub fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, SecureError> {
+        let plaintext = self.receive.decrypt(&nonce(self.receive_counter), ciphertext).map_err(|_| SecureError::Decrypt)?;
+        self.receive_counter += 1;
+        Ok(plaintext)
+    }
+}
+
+// Читает следующий кадр из соединения
+pub async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S, decoder: &mut FrameDecoder) -> Result<Vec<u8>, SecureError> {
+    let mut buffer = [0u8; 4096];
+    loop {
+        if let Some(frame) = decoder.decode()? {
+            return Ok(frame);
+        }
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 {
+            return Err(SecureError::Closed);
+        }
+        decoder.extend(&buffer[..n]);
+    }
+}
+
+/*
+    Выполняет рукопожатие. initiator - сторона, открывшая соединение.
+    Байты, прочитанные после рукопожатия, остаются в decoder.
+*/
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    decoder: &mut FrameDecoder,
+    identity: &Keypair,
+    initiator: bool,
+    max_frame_size: usize,
+) -> Result<SecureSession, SecureError> {
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    let local_ephemeral = X25519PublicKey::from(&secret).to_bytes();
+    write_frame(stream, &local_ephemeral, max_frame_size).await?;
+
+    let remote_frame = read_frame(stream, decoder).await?;
+    let remote_ephemeral: [u8; 32] = remote_frame
+        .as_slice()
+        .try_into()
+        .map_err(|_| SecureError::InvalidHandshake("ephemeral key must be 32 bytes".to_string()))?;
+    let shared = secret.diffie_hellman(&X25519PublicKey::from(remote_ephemeral));
+    // Ключ малого порядка дает предсказуемый общий секрет
+    if !shared.was_contributory() {
+        return Err(SecureError::InvalidHandshake("non-contributory ephemeral key".to_string()));
+    }
+
+    let (initiator_ephemeral, responder_ephemeral) = if initiator {
+        (local_ephemeral, remote_ephemeral)
+    } else {
+        (remote_ephemeral, local_ephemeral)
+    };
+    let (initiator_key, responder_key) = derive_keys(shared.as_bytes(), &initiator_ephemeral, &responder_ephemeral);
+    let mut session = if initiator {
+        SecureSession::new(initiator_key, responder_key)
+    } else {
+        SecureSession::new(responder_key, initiator_key)
+    };
+
+    let auth = AuthPayload {
+        node_id: BASE64.encode(identity.public.as_bytes()),
+        signature: BASE64.encode(identity.sign(&transcript(initiator, &initiator_ephemeral, &responder_ephemeral)).to_bytes()),
+    };
+    let auth = serde_json::to_vec(&auth).map_err(|e| SecureError::InvalidHandshake(e.to_string()))?;
+    let auth = session.encrypt(&auth)?;
+    write_frame(stream, &auth, max_frame_size).await?;
+
+    let remote_auth = read_frame(stream, decoder).await?;
+    let remote_auth = session.decrypt(&remote_auth)?;
+    let remote_auth: AuthPayload = serde_json::from_slice(&remote_auth).map_err(|e| SecureError::InvalidHandshake(e.to_string()))?;
+
+    let public_key = BASE64
+        .decode(&remote_auth.node_id)
+        .ok()
+        .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| SecureError::InvalidHandshake("invalid node id".to_string()))?;
+    let signature = BASE64
+        .decode(&remote_auth.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_bytes(&bytes).ok())
+        .ok_or(SecureError::InvalidSignature)?;
+    // Подпись удаленной стороны сделана в противоположной роли
+    let remote_transcript = transcript(!initiator, &initiator_ephemeral, &responder_ephemeral);
+    public_key.verify(&remote_transcript, &signature).map_err(|_| SecureError::InvalidSignature)?;
+
+    session.remote_id = remote_auth.node_id;
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SecretKey;
+    use crate::codec::DEFAULT_MAX_FRAME_SIZE;
+
+    fn keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    async fn connected_sessions() -> (SecureSession, SecureSession, Keypair, Keypair) {
+        let (mut left, mut right) = tokio::io::duplex(64 * 1024);
+        let (alice, bob) = (keypair(1), keypair(2));
+        let mut left_decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut right_decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+
+        let (initiator, responder) = tokio::join!(
+            handshake(&mut left, &mut left_decoder, &alice, true, DEFAULT_MAX_FRAME_SIZE),
+            handshake(&mut right, &mut right_decoder, &bob, false, DEFAULT_MAX_FRAME_SIZE),
+        );
+        (initiator.unwrap(), responder.unwrap(), alice, bob)
+    }
+
+    #[tokio::test]
+    async fn handshake_authenticates_both_sides() {
+        let (mut initiator, mut responder, alice, bob) = connected_sessions().await;
+        assert_eq!(initiator.remote_id, BASE64.encode(bob.public.as_bytes()));
+        assert_eq!(responder.remote_id, BASE64.encode(alice.public.as_bytes()));
+
+        let frame = initiator.encrypt(b"hello").unwrap();
+        assert_ne!(frame, b"hello".to_vec());
+        assert_eq!(responder.decrypt(&frame).unwrap(), b"hello".to_vec());
+
+        let reply = responder.encrypt(b"world").unwrap();
+        assert_eq!(initiator.decrypt(&reply).unwrap(), b"world".to_vec());
+    }
+
+    #[tokio::test]
+    async fn tampered_and_replayed_frames_are_rejected() {
+        let (mut initiator, mut responder, _, _) = connected_sessions().await;
+
+        let mut tampered = initiator.encrypt(b"first").unwrap();
+        tampered[0] ^= 1;
+        assert!(matches!(responder.decrypt(&tampered), Err(SecureError::Decrypt)));
+
+        let (mut initiator, mut responder, _, _) = connected_sessions().await;
+        let frame = initiator.encrypt(b"first").unwrap();
+        assert!(responder.decrypt(&frame).is_ok());
+        assert!(matches!(responder.decrypt(&frame), Err(SecureError::Decrypt)));
+    }
+
+    #[tokio::test]
+    async fn forged_identity_is_rejected() {
+        let (mut left, mut right) = tokio::io::duplex(64 * 1024);
+        let mut left_decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut right_decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let honest = keypair(1);
+
+        // Вторая сторона заявляет чужой ключ узла, но подписывает своим
+        let forger = async {
+            let secret = EphemeralSecret::random_from_rng(OsRng);
+            let local = X25519PublicKey::from(&secret).to_bytes();
+            write_frame(&mut right, &local, DEFAULT_MAX_FRAME_SIZE).await.unwrap();
+            let remote: [u8; 32] = read_frame(&mut right, &mut right_decoder).await.unwrap().as_slice().try_into().unwrap();
+            let shared = secret.diffie_hellman(&X25519PublicKey::from(remote));
+            let (initiator_key, responder_key) = derive_keys(shared.as_bytes(), &remote, &local);
+            let mut session = SecureSession::new(responder_key, initiator_key);
+            let auth = AuthPayload {
+                node_id: BASE64.encode(keypair(3).public.as_bytes()),
+                signature: BASE64.encode(keypair(4).sign(&transcript(false, &remote, &local)).to_bytes()),
+            };
+            let auth = session.encrypt(&serde_json::to_vec(&auth).unwrap()).unwrap();
+            write_frame(&mut right, &auth, DEFAULT_MAX_FRAME_SIZE).await.unwrap();
+        };
+
+        let (result, _) = tokio::join!(
+            handshake(&mut left, &mut left_decoder, &honest, true, DEFAULT_MAX_FRAME_SIZE),
+            forger,
+        );
+        assert!(matches!(result, Err(SecureError::InvalidSignature)));
+    }
+
+    #[tokio::test]
+    async fn reflected_signature_is_rejected() {
+        let (mut left, mut right) = tokio::io::duplex(64 * 1024);
+        let mut left_decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut right_decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let honest = keypair(1);
+
+        // Вторая сторона возвращает инициатору его же подтверждение ключа
+        let reflector = async {
+            let secret = EphemeralSecret::random_from_rng(OsRng);
+            let local = X25519PublicKey::from(&secret).to_bytes();
+            write_frame(&mut right, &local, DEFAULT_MAX_FRAME_SIZE).await.unwrap();
+            let remote: [u8; 32] = read_frame(&mut right, &mut right_decoder).await.unwrap().as_slice().try_into().unwrap();
+            let shared = secret.diffie_hellman(&X25519PublicKey::from(remote));
+            let (initiator_key, responder_key) = derive_keys(shared.as_bytes(), &remote, &local);
+            let mut session = SecureSession::new(responder_key, initiator_key);
+            let auth = read_frame(&mut right, &mut right_decoder).await.unwrap();
+            let auth = session.decrypt(&auth).unwrap();
+            let auth = session.encrypt(&auth).unwrap();
+            write_frame(&mut right, &auth, DEFAULT_MAX_FRAME_SIZE).await.unwrap();
+        };
+
+        let (result, _) = tokio::join!(
+            handshake(&mut left, &mut left_decoder, &honest, true, DEFAULT_MAX_FRAME_SIZE),
+            reflector,
+        );
+        assert!(matches!(result, Err(SecureError::InvalidSignature)));
+    }
+}
@@ -1,96 +1,125 @@
 /*
     Подключается к узлам в сети
-    Добавляет активное соединение в переменную connections
+    Поддерживает заданное количество исходящих соединений, выбирая адреса из таблицы известных узлов.
+    Если соединение не удалось или разорвано, адрес откладывается, и вместо него выбирается другой.
 */
-use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::Mutex;
-use std::sync::Arc;
-use log::{error, info};
-use crate::message::Message;
 use std::collections::HashSet;
-use std::env;
-use std::net::SocketAddr;
-use std::collections::HashMap;
-use serde_json::Error as SerdeError;
-use crate::message::MessageType;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
+use log::{info, warn};
+use serde_json::Value;
+use crate::message::{Message, MessageType};
+use crate::peer::{establish, run_peer, Direction, PeerContext};
+
+// Как часто проверяется количество исходящих соединений
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5);
+// Как часто запрашивать адреса у подключенных узлов, если подключаться не к кому
+const PEERS_REQUEST_INTERVAL: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub struct TCPConnect {}
+pub struct TCPConnect {
+    // Общие параметры соединений: список узлов, таблица адресов, очередь событий, размер кадра
+    context: PeerContext,
+    // Сколько исходящих соединений поддерживать
+    target_outbound: usize,
+    // Адреса, к которым сейчас установлено или устанавливается исходящее соединение
+    active: Arc<Mutex<HashSet<String>>>,
+}
 
 impl TCPConnect {
-    pub fn new() -> TCPConnect {
-        Self {}
+    pub fn new(context: PeerContext, target_outbound: usize) -> TCPConnect {
+        Self {
+            context,
+            target_outbound,
+            active: Arc::new(Mutex::new(HashSet::new())),
+        }
     }
 
+    // Требует запуска в отдельном потоке
     pub async fn connect_peers(&self) {
-        // Ограничить подключение к самому себе
-        match env::var("IS_MAIN") {
-            Ok(value) => 
-            {   
-                println!("Значение переменной окружения: {}", value);
-                if value == "MAIN" {
-                      return;
-                } 
-            },
-            Err(e) => println!("Не удалось прочитать переменную окружения {}", e),
+        // Узел, который только принимает соединения. Подключение к самому себе отсекается рукопожатием
+        if self.target_outbound == 0 {
+            info!("Outbound connections are disabled");
+            return;
         }
 
-        // Подключаемся к ноде N (становимся активным участником сети)
-        let mut stream = match TcpStream::connect("main_node:31313").await {
-            Ok(stream) => {
-                println!("Successfully connected to server");
-                stream
-            },
-            Err(e) => {
-                println!("Failed to connect to TCP server on 31313 {}", e);
-                return;
-            },
-        };
-
+        let mut last_peers_request: Option<Instant> = None;
         loop {
-            let mut buffer = vec![0; 1024];
-
-            match stream.read(&mut buffer).await {
-                Ok(n) if n == 0 => {
-                    return;
-                },
-                Ok(n) => {
-                    let message: Result<Message, SerdeError> = serde_json::from_slice(&buffer[..n]);
-
-                    match message {
-                        Ok(message) => {
-                            match message.message_type {
-                                MessageType::Connect => {
-                                    info!("Получено сообщения с запросом на подключение");
-                                    // Если подключений > 3, то перекинуть основное подключение к ним, т.к. это загрузочный узел (использовать HashSet) 
-                                },
-                                MessageType::Transaction => {
-                                    info!("Получено сообщение с транзакцией");
-                                },
-                                MessageType::Block => {
-                                    info!("Получено сообщение с блоком");
-                                },
-                                MessageType::Status => {
-                                    info!("Получено сообщение со статусом");
-                                    continue;
-                                },
-                            } 
-
-                            let response = Message::new(MessageType::Status, serde_json::json!({"status": "ok"}));
-                            let response_data = serde_json::to_vec(&response).unwrap();
-                            if let Err(e) = stream.write_all(&response_data).await {
-                                eprintln!("Failed to send response; error = {:?}", e);
-                            }
-                        },
-                        Err(e) => {
-                            error!("Failed to parse message; error = {:?}", e);
-                        }
+            let active = self.active.lock().await.clone();
+            let missing = self.target_outbound.saturating_sub(active.len());
+            if missing > 0 {
+                let candidates = self.context.table.lock().await.candidates(Instant::now(), &active, missing);
+                if candidates.is_empty() {
+                    // Известные адреса закончились: спрашиваем новые у подключенных узлов
+                    if last_peers_request.map(|at| at.elapsed() >= PEERS_REQUEST_INTERVAL).unwrap_or(true) {
+                        self.context.peers.broadcast(&Message::new(MessageType::GetPeers, Value::Null)).await;
+                        last_peers_request = Some(Instant::now());
                     }
-                },
-                Err(e) => {
-                    println!("Failed to read from socket; err = {:?}", e);
+                }
+                for address in candidates {
+                    self.active.lock().await.insert(address.clone());
+                    let context = self.context.clone();
+                    let active = Arc::clone(&self.active);
+                    tokio::spawn(async move {
+                        dial(address.clone(), context).await;
+                        active.lock().await.remove(&address);
+                    });
                 }
             }
+            sleep(MAINTENANCE_INTERVAL).await;
+        }
+    }
+}
+
+// Подключается к узлу и обслуживает соединение до его закрытия
+async fn dial(address: String, context: PeerContext) {
+    let mut stream = match timeout(CONNECT_TIMEOUT, TcpStream::connect(&address)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            warn!("Failed to connect to {}: {}", address, e);
+            context.table.lock().await.mark_failed(&address, Instant::now());
+            return;
+        },
+        Err(_) => {
+            warn!("Connection to {} timed out", address);
+            context.table.lock().await.mark_failed(&address, Instant::now());
+            return;
+        },
+    };
+
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(e) => {
+            warn!("Failed to get peer address of {}: {}", address, e);
+            context.table.lock().await.mark_failed(&address, Instant::now());
+            return;
         }
+    };
+
+    if let Some(ban) = context.reputation.banned(peer, None).await {
+        info!("Skipping banned peer {}: {}", address, ban.reason);
+        context.table.lock().await.mark_failed(&address, Instant::now());
+        return;
     }
-}
\ No newline at end of file
+
+    let (session, decoder, hello) = match establish(&mut stream, Direction::Outbound, &context).await {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("Handshake with {} failed: {}", address, e);
+            if let Some(misbehavior) = e.misbehavior() {
+                context.reputation.penalize_handshake(peer, Direction::Outbound, misbehavior).await;
+            }
+            context.table.lock().await.mark_failed(&address, Instant::now());
+            return;
+        }
+    };
+
+    info!("Connected to peer {} ({})", address, peer);
+    context.table.lock().await.mark_connected(&address);
+    let started = Instant::now();
+    run_peer(stream, peer, Direction::Outbound, context.clone(), session, decoder, hello).await;
+    context.table.lock().await.mark_disconnected(&address, Instant::now(), started.elapsed());
+}
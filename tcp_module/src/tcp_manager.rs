@@ -1,38 +1,27 @@
-use tokio::net::TcpStream;
-use tokio::io::{AsyncWriteExt, AsyncReadExt};
-use tokio::sync::Mutex;
-use std::sync::Arc;
-use log::{error, info, warn};
-use std::net::SocketAddr;
-use std::collections::HashMap;
-use crate::buffer::BufferMessage;
-use std::collections::HashSet;
-use crate::tcp_stream::TCPStream;
-use crate::message::{Message, BufMessage, MessageType};
-use serde_json::Error as SerdeError;
-use tokio::time::{self, Duration};
-use tokio::sync::broadcast::{Receiver, Sender};
+use log::info;
+use crate::buffer::{mark_seen, SeenMessages};
+use crate::message::Message;
+use crate::peer::Peers;
 use tokio::sync::mpsc::Receiver as ReceiverMPSC;
-use serde_json::to_string;
 
 pub struct TcpManager {
-    // Получает полученные от RPC сервера сообщения, отправленные напрямую на данный узел
+    // Сообщения основного проекта (RPC сервер, блокчейн) для рассылки всем узлам
     pub receiver_rpc: ReceiverMPSC<Message>,
 
     // Буффер всех полученных сообщений. Очищается автоматически.
-    pub buffer: Arc<Mutex<HashSet<BufMessage>>>,
+    pub buffer: SeenMessages,
 
-    // Broadcast очередь. Читают все входящие подключения
-    pub sender: Sender<String>
+    // Все подключенные узлы, входящие и исходящие
+    pub peers: Peers,
 }
 
 impl TcpManager {
     // Создает новый объект TCP Manager
-    pub fn new(buffer: Arc<Mutex<HashSet<BufMessage>>>, receiver_rpc: ReceiverMPSC<Message>, sender: Sender<String>) -> TcpManager {
+    pub fn new(buffer: SeenMessages, receiver_rpc: ReceiverMPSC<Message>, peers: Peers) -> TcpManager {
         TcpManager {
             receiver_rpc,
             buffer,
-            sender,
+            peers,
         }
     }
 
@@ -40,24 +29,12 @@ impl TcpManager {
     pub async fn start_thread(&mut self) {
         info!("TCP Manager started.");
 
-        // let mut failed_connections: Vec<SocketAddr> = Vec::new();
-
-        loop {
-            // Сообщения, полученные от RPC Server (local) -> Ретрансляция
-            while let Some(message) = self.receiver_rpc.recv().await {
-                match to_string(&message) {
-                    Ok(json_str) => {
-
-                        if let Err(e) = self.sender.send(json_str) {
-                            eprintln!("Failed to send message: {}", e);
-                            break;
-                        }
-                    }
-                    Err(e) => eprintln!("Ошибка преобразования в строку: {}", e),
-                }
-            }
-            // Сообщения, полученные от входящих соединений (external) -> Обработка
-            
+        // Сообщения, полученные от основного проекта (local) -> Рассылка.
+        // Сообщения от узлов (external) передаются в основной проект каждым соединением через NetworkEvent
+        while let Some(message) = self.receiver_rpc.recv().await {
+            // Собственное сообщение, вернувшееся от других узлов, не будет обработано повторно
+            mark_seen(&self.buffer, &message).await;
+            self.peers.broadcast(&message).await;
         }
     }
 }